@@ -1,17 +1,24 @@
-use std::{error::Error, path::PathBuf, str};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    str,
+};
 
 use clap::Args;
 use console::Style;
 use dialoguer::{theme::ColorfulTheme, Confirm, FuzzySelect};
+use prettytable::{row, Table};
 
 use crate::{
     cli::common::show_color_diff,
     config::ConfigurationHolder,
-    model::MachineContext,
-    repository::{Changes, DefaultEnvironment, Environment, Outcome, Repository},
+    repository::{
+        Backup, Changes, DefaultEnvironment, Environment, Outcome, Repository, BACKUPS_KEPT,
+    },
+    utils::git,
 };
 
-use super::common::{require_secret_keys, require_self};
+use super::common::{require_machine_context, require_secret_keys};
 
 #[derive(Debug, Args)]
 pub struct ApplyCommand {
@@ -21,6 +28,53 @@ pub struct ApplyCommand {
         help = "Only apply changes to specific config file/directory"
     )]
     only: Option<PathBuf>,
+    #[clap(
+        short,
+        long,
+        conflicts_with = "dry_run",
+        help = "Apply all changes without asking"
+    )]
+    yes: bool,
+    #[clap(short = 'n', long, help = "Only report changes, do not apply them")]
+    dry_run: bool,
+    #[clap(long, help = "Pull the repository with git (fast-forward only) first")]
+    pull: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Decision {
+    Apply,
+    Skip,
+}
+
+#[derive(Debug, Default)]
+struct ApplySummary {
+    created: usize,
+    changed: usize,
+    permission_only: usize,
+    skipped: usize,
+    not_applicable: usize,
+    errors: usize,
+}
+
+impl ApplySummary {
+    fn print(&self, dry_run: bool) {
+        let mut table = Table::new();
+
+        if dry_run {
+            table.add_row(row![H2 => "Summary (dry run)"]);
+        } else {
+            table.add_row(row![H2 => "Summary"]);
+        }
+        table.add_row(row!["Created", r->self.created]);
+        table.add_row(row!["Changed", r->self.changed]);
+        table.add_row(row!["Permission only", r->self.permission_only]);
+        table.add_row(row!["Skipped", r->self.skipped]);
+        table.add_row(row!["Not applicable", r->self.not_applicable]);
+        table.add_row(row!["Errors", r->self.errors]);
+
+        table.printstd();
+    }
 }
 
 impl ApplyCommand {
@@ -29,131 +83,312 @@ impl ApplyCommand {
         config: ConfigurationHolder,
         repository_path: PathBuf,
     ) -> Result<(), Box<dyn Error>> {
+        if self.pull {
+            git::pull(&repository_path)?;
+        }
+        if config.configuration.as_ref().is_some_and(|c| c.git) {
+            warn_working_copy(&repository_path)?;
+        }
+
         let repository = Repository::<DefaultEnvironment>::open(repository_path)?;
         let secret_keys = require_secret_keys(&config)?;
-        let context = MachineContext {
-            recipient: require_self(&config)?,
-            variables: config
-                .configuration
-                .map(|c| c.variables)
-                .unwrap_or_default(),
-        };
+        let context = require_machine_context(&config)?;
+        let mut summary = ApplySummary::default();
+        let mut backup = Backup::<DefaultEnvironment>::new(&config.backups_dir);
 
         for file in repository.files() {
+            if file.is_excluded(&secret_keys) {
+                continue;
+            }
+            match file.is_applicable(&context) {
+                Ok(true) => {}
+                Ok(false) => {
+                    if self.matches_only(&file.absolute_target()?) {
+                        summary.not_applicable += 1;
+                    }
+                    continue;
+                }
+                Err(error) => {
+                    let target = file.absolute_target()?;
+
+                    if self.matches_only(&target) {
+                        print_error(&target, error.as_ref());
+                        summary.errors += 1;
+                    }
+                    continue;
+                }
+            }
             let outcome = match file.outcome(&context, &secret_keys) {
                 Ok(outcome) => outcome,
                 Err(outcome_error) => {
-                    let red = Style::new().red();
-                    let bold = Style::new().bold();
-
-                    println!();
-                    println!(
-                        "{}: Skipping {} due to '{}'",
-                        red.apply_to("Error"),
-                        bold.apply_to(outcome_error.target.to_string_lossy()),
-                        outcome_error.error
-                    );
-                    println!();
+                    if !self.matches_only(&outcome_error.target) {
+                        continue;
+                    }
+                    print_error(&outcome_error.target, outcome_error.error.as_ref());
+                    summary.errors += 1;
                     continue;
                 }
             };
 
-            if !self
-                .only
-                .iter()
-                .all(|filter| outcome.target.starts_with(filter))
-            {
+            if !self.matches_only(&outcome.target) {
                 continue;
             }
 
-            match outcome.changes()? {
-                Changes::NewFile => confirm_new_file(&outcome)?,
-                Changes::Diff(current) => config_diff(&outcome, &current)?,
+            let changes = match outcome.changes() {
+                Ok(changes) => changes,
+                Err(error) => {
+                    print_error(&outcome.target, error.as_ref());
+                    summary.errors += 1;
+                    continue;
+                }
+            };
+            let decision = match &changes {
+                Changes::NewFile => self.confirm_new_file(&outcome)?,
+                Changes::Diff(current) => self.confirm_diff(&outcome, current)?,
                 Changes::ChangePermission(current_permission) => {
-                    config_set_permissions(&outcome, &current_permission)?
+                    self.confirm_set_permissions(&outcome, current_permission)?
                 }
+                Changes::Relink(current_link) => self.confirm_relink(&outcome, current_link)?,
                 Changes::None => continue,
             };
+
+            if decision == Decision::Skip {
+                summary.skipped += 1;
+                continue;
+            }
+            if !self.dry_run {
+                if let Err(error) = outcome.apply(&mut backup) {
+                    print_error(&outcome.target, error.as_ref());
+                    summary.errors += 1;
+                    continue;
+                }
+            }
+            match changes {
+                Changes::NewFile => summary.created += 1,
+                Changes::Diff(_) | Changes::Relink(_) => summary.changed += 1,
+                Changes::ChangePermission(_) => summary.permission_only += 1,
+                Changes::None => {}
+            }
+        }
+
+        println!();
+        summary.print(self.dry_run);
+
+        if !backup.is_empty() {
+            println!(
+                "Previous state saved in backup {} (use 'dotium restore' to roll back)",
+                Style::new().bold().apply_to(&backup.id)
+            );
+            Backup::<DefaultEnvironment>::prune(&config.backups_dir, BACKUPS_KEPT)?;
+        }
+
+        if summary.errors > 0 {
+            return Err(format!("{} file(s) could not be applied", summary.errors).into());
         }
 
         Ok(())
     }
-}
 
-fn confirm_new_file<E: Environment>(outcome: &Outcome<E>) -> Result<(), Box<dyn Error>> {
-    loop {
-        match FuzzySelect::with_theme(&ColorfulTheme::default())
-            .items(&["Yes", "Skip", "Show details", "Abort"])
+    fn matches_only(&self, target: &Path) -> bool {
+        self.only.iter().all(|filter| target.starts_with(filter))
+    }
+
+    fn confirm_new_file<E: Environment>(
+        &self,
+        outcome: &Outcome<E>,
+    ) -> Result<Decision, Box<dyn Error>> {
+        if self.dry_run {
+            println!("Would create {}", outcome.target.to_string_lossy());
+            return Ok(Decision::Apply);
+        }
+        if self.yes {
+            println!("Create {}", outcome.target.to_string_lossy());
+            return Ok(Decision::Apply);
+        }
+        loop {
+            match FuzzySelect::with_theme(&ColorfulTheme::default())
+                .items(&["Yes", "Skip", "Show details", "Abort"])
+                .with_prompt(format!(
+                    "Create new file {}",
+                    outcome.target.to_string_lossy()
+                ))
+                .default(0)
+                .interact_opt()?
+            {
+                Some(0) => return Ok(Decision::Apply),
+                Some(1) => return Ok(Decision::Skip),
+                Some(2) => {
+                    println!();
+                    println!("{}", outcome.target.to_string_lossy());
+                    println!("-------------------------------------------------------------------------------");
+                    println!(
+                        "{}",
+                        str::from_utf8(&outcome.content).unwrap_or("Binary content")
+                    );
+                    println!();
+                    println!("-------------------------------------------------------------------------------");
+                }
+                Some(3) => return Err("Aborted by user".into()),
+                None => return Err("Aborted by user".into()),
+                _ => (),
+            }
+        }
+    }
+
+    fn confirm_diff<E: Environment>(
+        &self,
+        outcome: &Outcome<E>,
+        current_content: &[u8],
+    ) -> Result<Decision, Box<dyn Error>> {
+        if self.dry_run {
+            println!("Would change {}", outcome.target.to_string_lossy());
+            return Ok(Decision::Apply);
+        }
+        if self.yes {
+            println!("Change {}", outcome.target.to_string_lossy());
+            return Ok(Decision::Apply);
+        }
+        loop {
+            match FuzzySelect::with_theme(&ColorfulTheme::default())
+                .items(&["Yes", "Skip", "Show details", "Abort"])
+                .with_prompt(format!("Change file {}", outcome.target.to_string_lossy()))
+                .default(0)
+                .interact_opt()?
+            {
+                Some(0) => return Ok(Decision::Apply),
+                Some(1) => return Ok(Decision::Skip),
+                Some(2) => {
+                    println!();
+                    println!("{}", outcome.target.to_string_lossy());
+                    println!("-------------------------------------------------------------------------------");
+                    show_color_diff(current_content, &outcome.content);
+                    println!("-------------------------------------------------------------------------------");
+                }
+                Some(3) => return Err("Aborted by user".into()),
+                None => return Err("Aborted by user".into()),
+                _ => (),
+            }
+        }
+    }
+
+    fn confirm_set_permissions<E: Environment>(
+        &self,
+        outcome: &Outcome<E>,
+        current_permission: &str,
+    ) -> Result<Decision, Box<dyn Error>> {
+        if self.dry_run {
+            println!(
+                "Would change permission of {} from {} to {}",
+                outcome.target.to_string_lossy(),
+                current_permission,
+                &outcome.permission
+            );
+            return Ok(Decision::Apply);
+        }
+        if self.yes {
+            println!(
+                "Change permission of {} from {} to {}",
+                outcome.target.to_string_lossy(),
+                current_permission,
+                &outcome.permission
+            );
+            return Ok(Decision::Apply);
+        }
+        match Confirm::with_theme(&ColorfulTheme::default())
             .with_prompt(format!(
-                "Create new file {}",
-                outcome.target.to_string_lossy()
+                "Change permission of {} from {} to {}",
+                outcome.target.to_string_lossy(),
+                current_permission,
+                &outcome.permission
             ))
-            .default(0)
+            .default(true)
             .interact_opt()?
         {
-            Some(0) => return outcome.apply(),
-            Some(1) => return Ok(()),
-            Some(2) => {
-                println!();
-                println!("{}", outcome.target.to_string_lossy());
-                println!("-------------------------------------------------------------------------------");
-                println!(
-                    "{}",
-                    str::from_utf8(&outcome.content).unwrap_or("Binary content")
-                );
-                println!();
-                println!("-------------------------------------------------------------------------------");
-            }
-            Some(3) => return Err("Aborted by user".into()),
-            None => return Err("Aborted by user".into()),
-            _ => (),
+            Some(true) => Ok(Decision::Apply),
+            Some(false) => Ok(Decision::Skip),
+            None => Err("Aborted by user".into()),
         }
     }
-}
 
-fn config_diff<E: Environment>(
-    outcome: &Outcome<E>,
-    current_content: &[u8],
-) -> Result<(), Box<dyn Error>> {
-    loop {
-        match FuzzySelect::with_theme(&ColorfulTheme::default())
-            .items(&["Yes", "Skip", "Show details", "Abort"])
-            .with_prompt(format!("Change file {}", outcome.target.to_string_lossy()))
-            .default(0)
+    fn confirm_relink<E: Environment>(
+        &self,
+        outcome: &Outcome<E>,
+        current_link: &Option<PathBuf>,
+    ) -> Result<Decision, Box<dyn Error>> {
+        let source = outcome.symlink.as_deref().unwrap_or(&outcome.target);
+        let message = format!(
+            "{} ({}) with link to {}",
+            outcome.target.to_string_lossy(),
+            link_description(current_link),
+            source.to_string_lossy()
+        );
+
+        if self.dry_run {
+            println!("Would replace {message}");
+            return Ok(Decision::Apply);
+        }
+        if self.yes {
+            println!("Replace {message}");
+            return Ok(Decision::Apply);
+        }
+        match Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Replace {message}"))
+            .default(current_link.is_some())
             .interact_opt()?
         {
-            Some(0) => return outcome.apply(),
-            Some(1) => return Ok(()),
-            Some(2) => {
-                println!();
-                println!("{}", outcome.target.to_string_lossy());
-                println!("-------------------------------------------------------------------------------");
-                show_color_diff(current_content, &outcome.content);
-                println!("-------------------------------------------------------------------------------");
-            }
-            Some(3) => return Err("Aborted by user".into()),
-            None => return Err("Aborted by user".into()),
-            _ => (),
+            Some(true) => Ok(Decision::Apply),
+            Some(false) => Ok(Decision::Skip),
+            None => Err("Aborted by user".into()),
         }
     }
 }
 
-fn config_set_permissions<E: Environment>(
-    outcome: &Outcome<E>,
-    current_permission: &str,
-) -> Result<(), Box<dyn Error>> {
-    match Confirm::with_theme(&ColorfulTheme::default())
-        .with_prompt(format!(
-            "Change permission of {} from {} to {}",
-            outcome.target.to_string_lossy(),
-            current_permission,
-            &outcome.permission
-        ))
-        .default(true)
-        .interact_opt()?
-    {
-        Some(true) => outcome.apply(),
-        Some(false) => Ok(()),
-        None => Err("Aborted by user".into()),
+fn link_description(current_link: &Option<PathBuf>) -> String {
+    match current_link {
+        Some(current_link) => format!("link to {}", current_link.to_string_lossy()),
+        None => "regular file".to_string(),
     }
 }
+
+fn print_error(target: &Path, error: &dyn Error) {
+    let red = Style::new().red();
+    let bold = Style::new().bold();
+
+    println!();
+    println!(
+        "{}: Skipping {} due to '{}'",
+        red.apply_to("Error"),
+        bold.apply_to(target.to_string_lossy()),
+        error
+    );
+    println!();
+}
+
+/// Warn about changes in the git working copy of the repository that are not shared yet.
+fn warn_working_copy(repository_path: &Path) -> Result<(), Box<dyn Error>> {
+    if !git::is_work_tree(repository_path) {
+        return Ok(());
+    }
+    let working_copy = git::working_copy(repository_path)?;
+
+    if !working_copy.unmerged.is_empty() {
+        println!(
+            "{}",
+            Style::new().red().apply_to(format!(
+                "Repository has unmerged changes, files may contain conflict markers: {}",
+                working_copy.unmerged.join(", ")
+            ))
+        );
+    }
+    if !working_copy.uncommitted.is_empty() {
+        println!(
+            "{}",
+            Style::new().yellow().apply_to(format!(
+                "Repository has uncommitted changes: {}",
+                working_copy.uncommitted.join(", ")
+            ))
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use std::{error::Error, path::PathBuf};
+
+use clap::Args;
+use console::Style;
+
+use crate::{
+    config::ConfigurationHolder,
+    repository::{CheckEntry, DefaultEnvironment, Issue, Repository},
+};
+
+use super::{common::optional_secret_keys, ReportedFailure};
+
+#[derive(Debug, Args)]
+pub struct CheckCommand {
+    #[clap(long, help = "Print issues as json")]
+    json: bool,
+}
+
+impl CheckCommand {
+    pub fn run(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let repository = Repository::<DefaultEnvironment>::open(repository_path)?;
+        let secret_keys = optional_secret_keys(&config)?;
+        if secret_keys.is_none() {
+            eprintln!(
+                "{}",
+                Style::new()
+                    .yellow()
+                    .apply_to("No keys file found, encrypted content is not checked")
+            );
+        }
+
+        let entries = repository.check(secret_keys.as_deref())?;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+        } else {
+            print_entries(&entries);
+        }
+
+        if !entries.is_empty() {
+            return Err(ReportedFailure.into());
+        }
+
+        Ok(())
+    }
+}
+
+fn print_entries(entries: &[CheckEntry]) {
+    let red = Style::new().red();
+
+    if entries.is_empty() {
+        println!("{}", Style::new().green().apply_to("No issues found"));
+        return;
+    }
+
+    for entry in entries {
+        let path = entry.path.to_string_lossy();
+
+        let line = match &entry.issue {
+            Issue::MissingSource => format!("  missing source:     {path}"),
+            Issue::OrphanFile => format!("  orphan file:        {path}"),
+            Issue::MissingDescriptor => format!("  missing descriptor: {path}"),
+            Issue::OutdatedFormat { format_version } => format!(
+                "  outdated format:    {path} (version {format_version}, use 'dotium migrate')"
+            ),
+            Issue::DuplicateTarget { target, sources } => format!(
+                "  duplicate target:   {} ({})",
+                target.to_string_lossy(),
+                sources.join(", ")
+            ),
+            Issue::InvalidPermission { permission } => {
+                format!("  invalid permission: {path} ({permission})")
+            }
+            Issue::InvalidCondition { message } => {
+                format!("  invalid condition:  {path} ({message})")
+            }
+            Issue::InvalidRecipients { message } => {
+                format!("  invalid recipients: {path} ({message})")
+            }
+            Issue::InvalidTemplate { message } => {
+                format!("  invalid template:   {path} ({message})")
+            }
+            Issue::CannotDecrypt { message } => format!("  cannot decrypt:     {path} ({message})"),
+            Issue::StaleEncryption => {
+                format!("  stale encryption:   {path} (use 'dotium reencrypt')")
+            }
+            Issue::Unreadable { message } => format!("  unreadable:         {path} ({message})"),
+        };
+        println!("{}", red.apply_to(line));
+    }
+}
@@ -1,9 +1,18 @@
-use std::{error::Error, fs, str};
+use std::{
+    env,
+    error::Error,
+    fs,
+    path::{Component, Path, PathBuf},
+    str,
+};
+
+use console::Style;
 
 use crate::{
     config::ConfigurationHolder,
-    model::{Recipient, SecretKey},
-    utils::color_diff::ColorDiff,
+    model::{MachineContext, Recipient, SecretKey},
+    repository::{DefaultEnvironment, Environment, Repository},
+    utils::{color_diff::ColorDiff, git},
 };
 
 pub fn require_self(config: &ConfigurationHolder) -> Result<Recipient, Box<dyn Error>> {
@@ -15,13 +24,40 @@ pub fn require_self(config: &ConfigurationHolder) -> Result<Recipient, Box<dyn E
     }
 }
 
+pub fn require_machine_context(
+    config: &ConfigurationHolder,
+) -> Result<MachineContext, Box<dyn Error>> {
+    Ok(MachineContext {
+        recipient: require_self(config)?,
+        variables: config
+            .configuration
+            .as_ref()
+            .map(|c| c.variables.clone())
+            .unwrap_or_default(),
+    })
+}
+
 pub fn require_secret_keys(config: &ConfigurationHolder) -> Result<Vec<SecretKey>, Box<dyn Error>> {
-    if !config.keys_file.is_file() {
+    let mut key_files = config
+        .configuration
+        .as_ref()
+        .map(|c| c.identity_files.clone())
+        .unwrap_or_default();
+    if config.keys_file.is_file() {
+        key_files.insert(0, config.keys_file.clone());
+    } else if key_files.is_empty() {
         return Err(
             "Keys file not initialized. Use 'dotium init' or create configuration manually".into(),
         );
     }
-    let secret_keys = SecretKey::read_from(fs::File::open(&config.keys_file)?)?;
+
+    let mut secret_keys = Vec::new();
+    for key_file in key_files {
+        secret_keys.extend(SecretKey::read_from(
+            fs::File::open(&key_file)?,
+            Some(key_file.to_string_lossy().to_string()),
+        )?);
+    }
 
     if secret_keys.is_empty() {
         Err("Keys file is empty. Use 'dotium init' or create configuration manually".into())
@@ -30,6 +66,22 @@ pub fn require_secret_keys(config: &ConfigurationHolder) -> Result<Vec<SecretKey
     }
 }
 
+/// Secret keys if the keys file or identity files are configured, `None` otherwise.
+pub fn optional_secret_keys(
+    config: &ConfigurationHolder,
+) -> Result<Option<Vec<SecretKey>>, Box<dyn Error>> {
+    let has_identities = config
+        .configuration
+        .as_ref()
+        .is_some_and(|c| !c.identity_files.is_empty());
+
+    if config.keys_file.is_file() || has_identities {
+        require_secret_keys(config).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
 pub fn show_color_diff(left: &[u8], right: &[u8]) {
     match (str::from_utf8(left), str::from_utf8(right)) {
         (Ok(left), Ok(right)) => {
@@ -39,3 +91,68 @@ pub fn show_color_diff(left: &[u8], right: &[u8]) {
         _ => println!("Binary content"),
     };
 }
+
+/// Absolute path of a path given on the command line.
+///
+/// Relative paths are resolved against the current directory, a leading `~` against the home
+/// directory. `.` and `..` are removed lexically, so paths to removed files work as well.
+pub fn absolute_path(path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let path = if let Ok(rest) = path.strip_prefix("~") {
+        DefaultEnvironment::home_dir()?.join(rest)
+    } else if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir()?.join(path)
+    };
+
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            component => result.push(component),
+        }
+    }
+    Ok(result)
+}
+
+/// Commit the files of the repository changed by a command, if the git integration is enabled.
+pub fn commit_changes<E: Environment>(
+    config: &ConfigurationHolder,
+    repository: &Repository<E>,
+    message: &str,
+) -> Result<(), Box<dyn Error>> {
+    if !config.configuration.as_ref().is_some_and(|c| c.git) {
+        return Ok(());
+    }
+    let directory = repository.directory();
+    if !git::is_work_tree(&directory) {
+        println!(
+            "{}",
+            Style::new()
+                .yellow()
+                .apply_to("Repository is not a git working copy, changes are not committed")
+        );
+        return Ok(());
+    }
+
+    if git::commit(&directory, &repository.changed_files(), message)? {
+        println!("Committed: {message}");
+    }
+
+    Ok(())
+}
+
+/// Short description of some targets for commit messages.
+pub fn describe_targets<P: AsRef<Path>>(targets: &[P]) -> String {
+    match targets.len() {
+        1..=3 => targets
+            .iter()
+            .map(|target| target.as_ref().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(", "),
+        count => format!("{count} files"),
+    }
+}
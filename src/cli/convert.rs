@@ -0,0 +1,113 @@
+use std::{error::Error, path::PathBuf};
+
+use clap::Args;
+use console::Style;
+use dialoguer::{theme::ColorfulTheme, Confirm};
+
+use crate::{
+    config::ConfigurationHolder,
+    model::FileAction,
+    repository::{DefaultEnvironment, Repository},
+};
+
+use super::common::{absolute_path, commit_changes, describe_targets, require_secret_keys};
+
+#[derive(Debug, Args)]
+pub struct ConvertCommand {
+    #[clap(help = "Tracked file or directory to convert")]
+    file_or_directory: PathBuf,
+    #[clap(short, long, value_enum, help = "New action of the file(s)")]
+    action: FileAction,
+}
+
+impl ConvertCommand {
+    pub fn run(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut repository = Repository::<DefaultEnvironment>::open(&repository_path)?;
+        let secret_keys = require_secret_keys(&config)?;
+        let path = absolute_path(&self.file_or_directory)?;
+
+        let mut files = Vec::new();
+        for file in repository.files() {
+            if file.absolute_target()?.starts_with(&path) && file.file.action != self.action {
+                files.push(file);
+            }
+        }
+        if files.is_empty() {
+            return Err(format!(
+                "No repository file to convert found for {}",
+                self.file_or_directory.to_string_lossy()
+            )
+            .into());
+        }
+        files.sort();
+
+        let bold = Style::new().bold();
+        println!(
+            "Convert files in repository {}",
+            bold.apply_to(&repository.directory().to_string_lossy())
+        );
+        for file_ref in &files {
+            println!(
+                "  {} ({:?} -> {:?})",
+                bold.apply_to(file_ref.file.target.to_string_lossy()),
+                file_ref.file.action,
+                self.action
+            );
+        }
+
+        println!();
+
+        if let Some(true) = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Continue")
+            .default(true)
+            .interact_opt()?
+        {
+            let mut converted = Vec::with_capacity(files.len());
+
+            for file_ref in &files {
+                // The source is about to change, so symlinks to it have to go first
+                if file_ref.file.action == FileAction::Symlink {
+                    file_ref.unlink_target()?;
+                }
+                converted.push(repository.change_action(file_ref, self.action, &secret_keys)?);
+            }
+            repository.store()?;
+
+            let targets = converted
+                .iter()
+                .map(|file_ref| file_ref.file.target.clone())
+                .collect::<Vec<_>>();
+            commit_changes(
+                &config,
+                &repository,
+                &format!(
+                    "convert {} to {}",
+                    describe_targets(&targets),
+                    self.action.name()
+                ),
+            )?;
+
+            for file_ref in converted {
+                println!(
+                    "  {} -> {} ({})",
+                    bold.apply_to(file_ref.file.target.to_string_lossy()),
+                    bold.apply_to(file_ref.to_string()),
+                    file_ref
+                        .file
+                        .permission
+                        .clone()
+                        .unwrap_or_else(|| file_ref.file.action.default_permission())
+                );
+                if file_ref.file.action == FileAction::Symlink {
+                    file_ref.link_target()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    str,
+};
+
+use clap::Args;
+use console::Style;
+
+use crate::{
+    cli::common::show_color_diff,
+    config::ConfigurationHolder,
+    model::{MachineContext, SecretKey},
+    repository::{Changes, DefaultEnvironment, FileRef, Repository},
+    utils::unified_diff::UnifiedDiff,
+};
+
+use super::{
+    common::{absolute_path, require_machine_context, require_secret_keys},
+    ReportedFailure,
+};
+
+#[derive(Debug, Args)]
+pub struct DiffCommand {
+    #[clap(help = "Only show differences of specific config files/directories")]
+    paths: Vec<PathBuf>,
+    #[clap(short, long, help = "Plain unified diff output (e.g. for patch)")]
+    unified: bool,
+    #[clap(
+        short,
+        long,
+        help = "Show differences from current config to repository (as seen by update)"
+    )]
+    reverse: bool,
+}
+
+impl DiffCommand {
+    pub fn run(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let repository = Repository::<DefaultEnvironment>::open(repository_path)?;
+        let secret_keys = require_secret_keys(&config)?;
+        let context = require_machine_context(&config)?;
+        let filters = self
+            .paths
+            .iter()
+            .map(|path| absolute_path(path))
+            .collect::<Result<Vec<PathBuf>, Box<dyn Error>>>()?;
+
+        let mut files = Vec::new();
+        for file in repository.files() {
+            if file.is_excluded(&secret_keys) {
+                continue;
+            }
+            let target = file.absolute_target()?;
+
+            if filters.is_empty() || filters.iter().any(|filter| target.starts_with(filter)) {
+                files.push(file);
+            }
+        }
+        files.sort_by(|a, b| a.file.target.cmp(&b.file.target));
+
+        let mut errors = 0;
+        for file in files {
+            let result = match file.is_applicable(&context) {
+                Ok(false) => continue,
+                Err(error) => Err(error),
+                Ok(true) if self.reverse => self.reverse_diff(&file, &context, &secret_keys),
+                Ok(true) => match file.outcome(&context, &secret_keys) {
+                    Ok(outcome) => outcome.changes().map(|changes| match changes {
+                        Changes::NewFile => self.print_diff(&file, None, &outcome.content, false),
+                        Changes::Diff(current) => {
+                            self.print_diff(&file, Some(&current), &outcome.content, false)
+                        }
+                        Changes::ChangePermission(current_permission) => {
+                            if !self.unified {
+                                print_permission(&file, &current_permission, &outcome.permission)
+                            }
+                        }
+                        Changes::Relink(Some(current_link)) => {
+                            if !self.unified {
+                                print_link(&file, &current_link)
+                            }
+                        }
+                        Changes::Relink(None) => match fs::read(&outcome.target) {
+                            Ok(current) if current != outcome.content => {
+                                self.print_diff(&file, Some(&current), &outcome.content, false)
+                            }
+                            _ => {}
+                        },
+                        Changes::None => {}
+                    }),
+                    Err(outcome_error) => Err(outcome_error.error),
+                },
+            };
+
+            if let Err(error) = result {
+                let red = Style::new().red();
+
+                errors += 1;
+                eprintln!(
+                    "{}: Unable to diff {} due to '{}'",
+                    red.apply_to("Error"),
+                    file.file.target.to_string_lossy(),
+                    error
+                );
+            }
+        }
+
+        if errors > 0 {
+            return Err(ReportedFailure.into());
+        }
+
+        Ok(())
+    }
+
+    fn reverse_diff(
+        &self,
+        file: &FileRef<DefaultEnvironment>,
+        context: &MachineContext,
+        secret_keys: &[SecretKey],
+    ) -> Result<(), Box<dyn Error>> {
+        // The output would not match the source (encrypted or a template), so patch cannot apply it
+        if self.unified && (file.file.action.is_crypted() || file.file.action.is_template()) {
+            eprintln!(
+                "{}: Skipping {}, its source cannot be patched",
+                Style::new().yellow().apply_to("Note"),
+                file
+            );
+            return Ok(());
+        }
+        let target = file.absolute_target()?;
+
+        if !target.exists() {
+            return Ok(());
+        }
+        let current_content = fs::read(target)?;
+
+        // Like update, templates are compared by their rendered content
+        if file.file.action.is_template() {
+            let rendered = file.get_rendered(context, secret_keys)?;
+
+            if rendered != current_content {
+                self.print_diff(file, Some(&rendered), &current_content, false);
+            }
+            return Ok(());
+        }
+        let repository_content = file.get_content(secret_keys)?;
+
+        if repository_content != current_content {
+            self.print_diff(file, Some(&repository_content), &current_content, true);
+        }
+
+        Ok(())
+    }
+
+    fn print_diff(
+        &self,
+        file: &FileRef<DefaultEnvironment>,
+        old: Option<&[u8]>,
+        new: &[u8],
+        in_repository: bool,
+    ) {
+        let name = if in_repository {
+            file.to_string()
+        } else {
+            file.file.target.to_string_lossy().to_string()
+        };
+
+        if self.unified {
+            let old_name = match old {
+                Some(_) => format!("a/{name}"),
+                None => "/dev/null".to_string(),
+            };
+            let new_name = format!("b/{name}");
+
+            match (str::from_utf8(old.unwrap_or_default()), str::from_utf8(new)) {
+                (Ok(old), Ok(new)) => {
+                    print!("{}", UnifiedDiff::new(&old_name, &new_name, old, new))
+                }
+                _ => println!("Binary files {old_name} and {new_name} differ"),
+            }
+        } else {
+            let bold = Style::new().bold();
+
+            println!();
+            println!("{}", bold.apply_to(name));
+            println!(
+                "-------------------------------------------------------------------------------"
+            );
+            show_color_diff(old.unwrap_or_default(), new);
+            println!(
+                "-------------------------------------------------------------------------------"
+            );
+        }
+    }
+}
+
+fn print_permission(
+    file: &FileRef<DefaultEnvironment>,
+    current_permission: &str,
+    permission: &str,
+) {
+    let bold = Style::new().bold();
+
+    println!();
+    println!(
+        "{}: permission {} -> {}",
+        bold.apply_to(file.file.target.to_string_lossy()),
+        current_permission,
+        permission
+    );
+}
+
+fn print_link(file: &FileRef<DefaultEnvironment>, current_link: &Path) {
+    let bold = Style::new().bold();
+
+    println!();
+    println!(
+        "{}: link to {}",
+        bold.apply_to(file.file.target.to_string_lossy()),
+        current_link.to_string_lossy()
+    );
+}
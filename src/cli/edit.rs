@@ -8,7 +8,7 @@ use crate::{
     repository::{DefaultEnvironment, FileRef, Repository},
 };
 
-use super::common::require_secret_keys;
+use super::common::{commit_changes, require_secret_keys};
 
 #[derive(Debug, Args)]
 pub struct EditCommand {
@@ -37,6 +37,11 @@ impl EditCommand {
 
                 if let Some(new_content) = Editor::new().trim_newlines(false).edit(content)? {
                     file.set_content(new_content.as_bytes())?;
+                    commit_changes(
+                        &config,
+                        &repository,
+                        &format!("edit {}", file.file.target.to_string_lossy()),
+                    )?;
                 }
 
                 return Ok(());
@@ -56,6 +61,11 @@ impl EditCommand {
 
             if let Some(new_content) = Editor::new().trim_newlines(false).edit(content)? {
                 file.set_content(new_content.as_bytes())?;
+                commit_changes(
+                    &config,
+                    &repository,
+                    &format!("edit {}", file.file.target.to_string_lossy()),
+                )?;
             }
         }
 
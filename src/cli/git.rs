@@ -0,0 +1,51 @@
+use std::{error::Error, path::PathBuf};
+
+use clap::{Args, Subcommand};
+use console::Style;
+
+use crate::{config::ConfigurationHolder, utils::git};
+
+#[derive(Debug, Subcommand)]
+pub enum GitSubCommand {
+    #[clap(about = "Commit changes to the repository and check it before apply")]
+    Enable,
+    #[clap(about = "Leave git to the user")]
+    Disable,
+}
+
+#[derive(Debug, Args)]
+pub struct GitCommand {
+    #[clap(subcommand)]
+    subcommand: GitSubCommand,
+}
+
+impl GitCommand {
+    pub fn run(
+        &self,
+        mut config: ConfigurationHolder,
+        repository_path: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let configuration = config
+            .configuration
+            .as_mut()
+            .ok_or("Dotium not initialized. Use 'dotium init' or create configuration manually")?;
+
+        match self.subcommand {
+            GitSubCommand::Enable => {
+                configuration.git = true;
+                if !git::is_work_tree(&repository_path) {
+                    println!(
+                        "{}",
+                        Style::new().yellow().apply_to(format!(
+                            "{} is not a git working copy",
+                            repository_path.to_string_lossy()
+                        ))
+                    );
+                }
+            }
+            GitSubCommand::Disable => configuration.git = false,
+        }
+
+        config.store()
+    }
+}
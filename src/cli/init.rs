@@ -1,4 +1,4 @@
-use std::error::Error;
+use std::{error::Error, path::PathBuf};
 
 use clap::Args;
 use console::Style;
@@ -11,6 +11,17 @@ use crate::config::ConfigurationHolder;
 pub struct InitCommand {
     #[clap(short, long, help = "Name of the host")]
     pub name: Option<String>,
+    #[clap(
+        long,
+        help = "Use an existing SSH private key instead of generating an age key"
+    )]
+    pub ssh_key: Option<PathBuf>,
+    #[clap(
+        long,
+        conflicts_with = "ssh_key",
+        help = "Protect the keys file with a passphrase"
+    )]
+    pub protect: bool,
 }
 
 impl InitCommand {
@@ -32,10 +43,16 @@ impl InitCommand {
             "  Config file : {}",
             bold.apply_to(config.config_file.to_string_lossy())
         );
-        println!(
-            "  Keys file   : {}",
-            bold.apply_to(config.keys_file.to_string_lossy())
-        );
+        match &self.ssh_key {
+            Some(ssh_key) => println!(
+                "  SSH key     : {}",
+                bold.apply_to(ssh_key.to_string_lossy())
+            ),
+            None => println!(
+                "  Keys file   : {}",
+                bold.apply_to(config.keys_file.to_string_lossy())
+            ),
+        }
         println!();
 
         if let Some(true) = Confirm::with_theme(&ColorfulTheme::default())
@@ -43,7 +60,7 @@ impl InitCommand {
             .default(true)
             .interact_opt()?
         {
-            config.init(&name)?;
+            config.init(&name, self.ssh_key.as_deref(), self.protect)?;
         }
 
         Ok(())
@@ -0,0 +1,61 @@
+use std::{error::Error, fs};
+
+use clap::{Args, Subcommand};
+
+use crate::{
+    config::ConfigurationHolder,
+    model::{
+        is_protected, protect_keys, request_new_passphrase, request_passphrase, unprotect_keys,
+    },
+};
+
+#[derive(Debug, Subcommand)]
+pub enum KeysSubCommand {
+    #[clap(about = "Protect the keys file with a passphrase")]
+    Protect,
+    #[clap(about = "Remove the passphrase protection of the keys file")]
+    Unprotect,
+}
+
+#[derive(Debug, Args)]
+pub struct KeysCommand {
+    #[clap(subcommand)]
+    subcommand: KeysSubCommand,
+}
+
+impl KeysCommand {
+    pub fn run(&self, config: ConfigurationHolder) -> Result<(), Box<dyn Error>> {
+        if !config.keys_file.is_file() {
+            return Err(
+                "Keys file not initialized. Use 'dotium init' or create configuration manually"
+                    .into(),
+            );
+        }
+        let content = fs::read(&config.keys_file)?;
+
+        match self.subcommand {
+            KeysSubCommand::Protect => {
+                if is_protected(&content) {
+                    return Err("Keys file is already protected".into());
+                }
+                let passphrase = request_new_passphrase(&format!(
+                    "New passphrase for {}",
+                    config.keys_file.to_string_lossy()
+                ))?;
+
+                config.store_keys(&protect_keys(&content, passphrase)?)
+            }
+            KeysSubCommand::Unprotect => {
+                if !is_protected(&content) {
+                    return Err("Keys file is not protected".into());
+                }
+                let passphrase = request_passphrase(&format!(
+                    "Passphrase for {}",
+                    config.keys_file.to_string_lossy()
+                ))?;
+
+                config.store_keys(&unprotect_keys(&content, passphrase)?)
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::{error::Error, path::PathBuf};
+
+use clap::Args;
+use console::Style;
+use dialoguer::{theme::ColorfulTheme, Confirm};
+
+use crate::{
+    config::ConfigurationHolder,
+    repository::{DefaultEnvironment, Repository},
+};
+
+use super::common::commit_changes;
+
+#[derive(Debug, Args)]
+pub struct MigrateCommand {
+    #[clap(short = 'n', long, help = "Only show the necessary steps")]
+    dry_run: bool,
+}
+
+impl MigrateCommand {
+    pub fn run(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut repository = Repository::<DefaultEnvironment>::open(&repository_path)?;
+        let migrations = repository.migrations()?;
+
+        let bold = Style::new().bold();
+        if migrations.is_empty() {
+            println!(
+                "Repository {} is up to date (format version {})",
+                bold.apply_to(&repository.directory().to_string_lossy()),
+                repository.format_version()
+            );
+            return Ok(());
+        }
+
+        println!(
+            "Migrate repository {}",
+            bold.apply_to(&repository.directory().to_string_lossy())
+        );
+        for migration in &migrations {
+            println!("  {migration}");
+        }
+
+        println!();
+
+        if self.dry_run {
+            return Ok(());
+        }
+        if let Some(true) = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Continue")
+            .default(true)
+            .interact_opt()?
+        {
+            repository.migrate(&migrations)?;
+            repository.store()?;
+
+            commit_changes(
+                &config,
+                &repository,
+                &format!("migrate to format version {}", repository.format_version()),
+            )?;
+        }
+
+        Ok(())
+    }
+}
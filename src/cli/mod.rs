@@ -1,18 +1,29 @@
-use std::{error::Error, path::PathBuf};
+use std::{error::Error, fmt, path::PathBuf};
 
 use clap::Subcommand;
 
 use crate::config::ConfigurationHolder;
 
 mod apply;
+mod check;
 mod common;
 mod completions;
+mod convert;
+mod diff;
 mod edit;
 mod gen_key;
+mod git;
 mod init;
 mod init_repo;
+mod keys;
+mod migrate;
 mod recipients;
+mod reencrypt;
+mod restore;
+mod secrets;
+mod status;
 mod track;
+mod untrack;
 mod update;
 mod variables;
 
@@ -20,20 +31,42 @@ mod variables;
 pub enum MainCommand {
     #[clap(about = "Apply repository contents to current config")]
     Apply(apply::ApplyCommand),
+    #[clap(about = "Validate the repository without touching the config (exit code 1 on issues)")]
+    Check(check::CheckCommand),
     #[clap(about = "Generate shell completions")]
     Completions(completions::CompletionCommand),
+    #[clap(about = "Change the action of tracked files (e.g. as-is to crypted)")]
+    Convert(convert::ConvertCommand),
+    #[clap(about = "Show differences between repository and current config")]
+    Diff(diff::DiffCommand),
     #[clap(about = "Edit a file in the repository")]
     Edit(edit::EditCommand),
     #[clap(about = "Generate new age-compatible public/private key pair")]
     GenKey(gen_key::GenKeyCommand),
+    #[clap(about = "Enable or disable committing repository changes with git")]
+    Git(git::GitCommand),
     #[clap(about = "Initialize dotium configuration on new machine")]
     Init(init::InitCommand),
     #[clap(about = "Initialize a new repository")]
     InitRepo(init_repo::InitRepoCommand),
+    #[clap(about = "Manage the secret keys file")]
+    Keys(keys::KeysCommand),
+    #[clap(about = "Upgrade the repository to the current format version")]
+    Migrate(migrate::MigrateCommand),
     #[clap(about = "Manage recipients of a repository")]
     Recipients(recipients::RecipientsCommand),
+    #[clap(about = "Encrypt files again for their current recipients")]
+    Reencrypt(reencrypt::ReencryptCommand),
+    #[clap(about = "Restore files from backups taken by apply")]
+    Restore(restore::RestoreCommand),
+    #[clap(about = "Manage secret variables for templates")]
+    Secrets(secrets::SecretsCommand),
+    #[clap(about = "Show which tracked files differ from the repository (exit code 1 on drift)")]
+    Status(status::StatusCommand),
     #[clap(about = "Track dot-file (i.e. add it to repository")]
     Track(track::TrackCommand),
+    #[clap(about = "Untrack dot-file (i.e. remove it from repository)")]
+    Untrack(untrack::UntrackCommand),
     #[clap(about = "Update repository file from current config (reverse of apply)")]
     Update(update::UpdateCommand),
     #[clap(about = "Manage variables")]
@@ -48,15 +81,38 @@ impl MainCommand {
     ) -> Result<(), Box<dyn Error>> {
         match self {
             MainCommand::Apply(cmd) => cmd.run(config, repository_path),
+            MainCommand::Check(cmd) => cmd.run(config, repository_path),
             MainCommand::GenKey(cmd) => cmd.run(),
+            MainCommand::Git(cmd) => cmd.run(config, repository_path),
             MainCommand::Completions(cmd) => cmd.run(),
+            MainCommand::Convert(cmd) => cmd.run(config, repository_path),
+            MainCommand::Diff(cmd) => cmd.run(config, repository_path),
             MainCommand::Edit(cmd) => cmd.run(config, repository_path),
             MainCommand::Init(cmd) => cmd.run(config),
             MainCommand::InitRepo(cmd) => cmd.run(config, repository_path),
+            MainCommand::Keys(cmd) => cmd.run(config),
+            MainCommand::Migrate(cmd) => cmd.run(config, repository_path),
             MainCommand::Recipients(cmd) => cmd.run(config, repository_path),
-            MainCommand::Track(cmd) => cmd.run(repository_path),
+            MainCommand::Reencrypt(cmd) => cmd.run(config, repository_path),
+            MainCommand::Restore(cmd) => cmd.run(config),
+            MainCommand::Secrets(cmd) => cmd.run(config, repository_path),
+            MainCommand::Status(cmd) => cmd.run(config, repository_path),
+            MainCommand::Track(cmd) => cmd.run(config, repository_path),
+            MainCommand::Untrack(cmd) => cmd.run(config, repository_path),
             MainCommand::Update(cmd) => cmd.run(config, repository_path),
-            MainCommand::Variables(cmd) => cmd.run(config),
+            MainCommand::Variables(cmd) => cmd.run(config, repository_path),
         }
     }
 }
+
+/// Failure of a command that has already reported its findings, only the exit code is missing.
+#[derive(Debug)]
+pub struct ReportedFailure;
+
+impl Error for ReportedFailure {}
+
+impl fmt::Display for ReportedFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Problems found")
+    }
+}
@@ -1,24 +1,66 @@
 use std::{error::Error, path::PathBuf};
 
 use clap::{Args, Subcommand};
-use dialoguer::{theme::ColorfulTheme, Confirm};
+use console::Style;
+use dialoguer::{theme::ColorfulTheme, Confirm, FuzzySelect};
 use prettytable::{row, Table};
 
 use crate::{
     config::ConfigurationHolder,
-    repository::{DefaultEnvironment, Repository},
+    model::Recipient,
+    repository::{DefaultEnvironment, Repository, SECRETS_FILE},
 };
 
-use super::common::{require_secret_keys, require_self};
+use super::common::{
+    absolute_path, commit_changes, describe_targets, require_secret_keys, require_self,
+};
+
+#[derive(Debug, Args)]
+pub struct RevokeArgs {
+    #[clap(help = "Name or key of the recipient to revoke")]
+    pub recipient: String,
+}
+
+#[derive(Debug, Args)]
+pub struct GroupArgs {
+    #[clap(help = "Name of the group")]
+    pub group: String,
+    #[clap(help = "Names of the recipients in the group (none removes the group)")]
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ScopeArgs {
+    #[clap(help = "Encrypted file or directory to restrict")]
+    pub file_or_directory: PathBuf,
+    #[clap(help = "Recipient names or groups (none: all recipients)")]
+    pub recipients: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct RejectArgs {
+    #[clap(help = "Name or key of the request to reject")]
+    pub recipient: Option<String>,
+}
 
 #[derive(Debug, Subcommand)]
 pub enum RecipientsSubCommand {
     #[clap(about = "List recipients of repository", alias = "ls")]
     List,
-    #[clap(about = "Approve pending recipient requests")]
+    #[clap(about = "Approve, reject or defer pending recipient requests")]
     Approve,
+    #[clap(about = "Reject a pending recipient request")]
+    Reject(RejectArgs),
     #[clap(about = "Add a recipient request for self")]
     AddSelf,
+    #[clap(about = "Withdraw the recipient request for self")]
+    Withdraw,
+    #[clap(about = "Revoke a recipient and re-encrypt all secrets for the remaining ones")]
+    Revoke(RevokeArgs),
+    #[clap(about = "Set the members of a recipient group")]
+    Group(GroupArgs),
+    #[clap(about = "Restrict encrypted files to some recipients or groups")]
+    Scope(ScopeArgs),
 }
 
 #[derive(Debug, Args)]
@@ -36,7 +78,12 @@ impl RecipientsCommand {
         match &self.subcommand {
             RecipientsSubCommand::List => self.list(repository_path),
             RecipientsSubCommand::Approve => self.approve(config, repository_path),
+            RecipientsSubCommand::Reject(args) => self.reject(config, repository_path, args),
             RecipientsSubCommand::AddSelf => self.add_self(config, repository_path),
+            RecipientsSubCommand::Withdraw => self.withdraw(config, repository_path),
+            RecipientsSubCommand::Revoke(args) => self.revoke(config, repository_path, args),
+            RecipientsSubCommand::Group(args) => self.group(config, repository_path, args),
+            RecipientsSubCommand::Scope(args) => self.scope(config, repository_path, args),
         }
     }
 
@@ -52,6 +99,19 @@ impl RecipientsCommand {
 
         table.printstd();
 
+        let mut groups_table = Table::new();
+        let mut has_groups = false;
+        groups_table.add_row(row![H2 => "Groups"]);
+
+        for (group, members) in repository.groups() {
+            has_groups = true;
+            groups_table.add_row(row![group, members.join(", ")]);
+        }
+
+        if has_groups {
+            groups_table.printstd();
+        }
+
         let mut requests_table = Table::new();
         let mut has_requests = false;
         requests_table.add_row(row![H2 => "Recipients requests"]);
@@ -77,20 +137,122 @@ impl RecipientsCommand {
         let secret_keys = require_secret_keys(&config)?;
 
         let mut approved = vec![];
+        let mut rejected = vec![];
 
         for recipient in repository.recipient_requests() {
-            match Confirm::with_theme(&ColorfulTheme::default())
-                .with_prompt(format!("Approve {recipient}"))
+            match FuzzySelect::with_theme(&ColorfulTheme::default())
+                .items(&["Approve", "Reject", "Defer"])
+                .with_prompt(format!("Request of {recipient}"))
+                .default(2)
                 .interact_opt()?
             {
-                Some(true) => approved.push(recipient.clone()),
-                Some(false) => {}
+                Some(0) => approved.push(recipient.clone()),
+                Some(1) => rejected.push(recipient.clone()),
+                Some(_) => {}
                 None => return Err("Aborted by user".into()),
             }
         }
+        for recipient in &rejected {
+            repository.reject_recipient_request(&recipient.key)?;
+        }
         if !approved.is_empty() {
             repository.approve_recipients(&approved, &secret_keys)?;
+        } else if !rejected.is_empty() {
+            repository.store()?;
+        }
+
+        let mut changes = Vec::new();
+        if !approved.is_empty() {
+            changes.push(format!("approve {}", recipient_names(&approved)));
+        }
+        if !rejected.is_empty() {
+            changes.push(format!("reject {}", recipient_names(&rejected)));
+        }
+        if !changes.is_empty() {
+            commit_changes(
+                &config,
+                &repository,
+                &format!("recipients: {}", changes.join(", ")),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn reject(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+        args: &RejectArgs,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut repository = Repository::<DefaultEnvironment>::open(&repository_path)?;
+
+        let recipient = match &args.recipient {
+            Some(recipient) => recipient.clone(),
+            None => {
+                let requests = repository
+                    .recipient_requests()
+                    .cloned()
+                    .collect::<Vec<Recipient>>();
+
+                if requests.is_empty() {
+                    return Err("No pending recipient requests".into());
+                }
+                match FuzzySelect::with_theme(&ColorfulTheme::default())
+                    .items(&requests)
+                    .with_prompt("Select request to reject")
+                    .interact_opt()?
+                {
+                    Some(index) => requests[index].key.clone(),
+                    None => return Err("Aborted by user".into()),
+                }
+            }
+        };
+
+        let rejected = repository.reject_recipient_request(&recipient)?;
+        repository.store()?;
+        commit_changes(
+            &config,
+            &repository,
+            &format!("recipients: reject {}", rejected.name),
+        )?;
+
+        println!("Rejected {}", Style::new().bold().apply_to(&rejected));
+
+        Ok(())
+    }
+
+    fn withdraw(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let recipient = require_self(&config)?;
+        let mut repository = Repository::<DefaultEnvironment>::open(&repository_path)?;
+
+        if !repository
+            .recipient_requests()
+            .any(|r| r.key == recipient.key)
+        {
+            return Err("Repository has no recipient request for self".into());
+        }
+
+        if let Some(true) = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "Withdraw request for self from repository {}",
+                repository.directory().to_string_lossy()
+            ))
+            .default(true)
+            .interact_opt()?
+        {
+            repository.reject_recipient_request(&recipient.key)?;
+            repository.store()?;
+            commit_changes(
+                &config,
+                &repository,
+                &format!("recipients: withdraw {}", recipient.name),
+            )?;
         }
+
         Ok(())
     }
 
@@ -120,10 +282,191 @@ impl RecipientsCommand {
             .default(true)
             .interact_opt()?
         {
+            let message = format!("recipients: request {}", recipient.name);
+
             repository.add_recipient_request(recipient);
             repository.store()?;
+            commit_changes(&config, &repository, &message)?;
+        }
+
+        Ok(())
+    }
+
+    fn revoke(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+        args: &RevokeArgs,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut repository = Repository::<DefaultEnvironment>::open(&repository_path)?;
+        let secret_keys = require_secret_keys(&config)?;
+
+        let recipient = repository
+            .recipients()
+            .find(|r| r.name == args.recipient || r.key == args.recipient)
+            .cloned();
+        let mut secrets = repository
+            .files()
+            .filter(|f| f.file.action.is_crypted())
+            .filter(|f| {
+                recipient.as_ref().is_none_or(|recipient| {
+                    f.repository
+                        .recipients_for(&f.file)
+                        .is_ok_and(|recipients| recipients.iter().any(|r| r.key == recipient.key))
+                })
+            })
+            .map(|f| f.file.target)
+            .collect::<Vec<PathBuf>>();
+        secrets.sort();
+        // Secrets are always encrypted for all recipients
+        let secret_names = repository
+            .secrets(&secret_keys)?
+            .into_keys()
+            .collect::<Vec<String>>();
+
+        if let Some(true) = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "Revoke {} and re-encrypt {} secret file(s)",
+                args.recipient,
+                secrets.len() + usize::from(!secret_names.is_empty())
+            ))
+            .default(false)
+            .interact_opt()?
+        {
+            let revoked = repository.revoke_recipient(&args.recipient, &secret_keys)?;
+            let bold = Style::new().bold();
+            let yellow = Style::new().yellow();
+
+            println!("Revoked {}", bold.apply_to(&revoked));
+            commit_changes(
+                &config,
+                &repository,
+                &format!("recipients: revoke {}", revoked.name),
+            )?;
+            if !secrets.is_empty() || !secret_names.is_empty() {
+                println!();
+                println!(
+                    "{}",
+                    yellow.apply_to(format!(
+                        "{} could read these secrets before (including older versions in the repository history), they should be rotated:",
+                        revoked.name
+                    ))
+                );
+                for secret in secrets {
+                    println!("  {}", secret.to_string_lossy());
+                }
+                if !secret_names.is_empty() {
+                    println!("  {} ({})", SECRETS_FILE, secret_names.join(", "));
+                }
+            }
         }
 
         Ok(())
     }
+
+    fn group(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+        args: &GroupArgs,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut repository = Repository::<DefaultEnvironment>::open(&repository_path)?;
+        let secret_keys = require_secret_keys(&config)?;
+
+        repository.set_group(&args.group, args.members.clone(), &secret_keys)?;
+        commit_changes(
+            &config,
+            &repository,
+            &format!("recipients: group {}", args.group),
+        )?;
+
+        let bold = Style::new().bold();
+        if args.members.is_empty() {
+            println!("Removed group {}", bold.apply_to(&args.group));
+        } else {
+            println!(
+                "Group {}: {}",
+                bold.apply_to(&args.group),
+                args.members.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+
+    fn scope(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+        args: &ScopeArgs,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut repository = Repository::<DefaultEnvironment>::open(&repository_path)?;
+        let secret_keys = require_secret_keys(&config)?;
+        let path = absolute_path(&args.file_or_directory)?;
+        let recipients = Some(args.recipients.clone()).filter(|r| !r.is_empty());
+
+        let mut files = Vec::new();
+        for file in repository.files() {
+            if file.absolute_target()?.starts_with(&path)
+                && file.file.action.is_crypted()
+                && file.file.recipients != recipients
+            {
+                files.push(file);
+            }
+        }
+        if files.is_empty() {
+            return Err(format!(
+                "No encrypted repository file to restrict found for {}",
+                args.file_or_directory.to_string_lossy()
+            )
+            .into());
+        }
+        files.sort();
+
+        let bold = Style::new().bold();
+        let scope = match &recipients {
+            Some(recipients) => recipients.join(", "),
+            None => "all recipients".to_string(),
+        };
+        println!("Restrict files to {}", bold.apply_to(&scope));
+        for file_ref in &files {
+            println!(
+                "  {}",
+                bold.apply_to(file_ref.file.target.to_string_lossy())
+            );
+        }
+
+        println!();
+
+        if let Some(true) = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Continue")
+            .default(true)
+            .interact_opt()?
+        {
+            for file_ref in &files {
+                repository.change_recipients(file_ref, recipients.clone(), &secret_keys)?;
+            }
+            repository.store()?;
+
+            let targets = files
+                .iter()
+                .map(|file_ref| &file_ref.file.target)
+                .collect::<Vec<_>>();
+            commit_changes(
+                &config,
+                &repository,
+                &format!("restrict {} to {scope}", describe_targets(&targets)),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn recipient_names(recipients: &[Recipient]) -> String {
+    recipients
+        .iter()
+        .map(|r| r.name.as_str())
+        .collect::<Vec<&str>>()
+        .join(", ")
 }
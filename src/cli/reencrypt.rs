@@ -0,0 +1,87 @@
+use std::{error::Error, path::PathBuf};
+
+use clap::Args;
+use console::Style;
+
+use crate::{
+    config::ConfigurationHolder,
+    repository::{DefaultEnvironment, Repository},
+};
+
+use super::common::{commit_changes, require_secret_keys};
+
+#[derive(Debug, Args)]
+pub struct ReencryptCommand {
+    #[clap(
+        short,
+        long,
+        help = "Only re-encrypt files that are not encrypted for their current recipients"
+    )]
+    stale_only: bool,
+    #[clap(
+        short = 'n',
+        long,
+        help = "Only show the files, do not re-encrypt them"
+    )]
+    dry_run: bool,
+}
+
+impl ReencryptCommand {
+    pub fn run(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let repository = Repository::<DefaultEnvironment>::open(repository_path)?;
+        let secret_keys = require_secret_keys(&config)?;
+        let yellow = Style::new().yellow();
+
+        let mut files = Vec::new();
+        for file in repository.files() {
+            if !file.file.action.is_crypted() || (self.stale_only && !file.is_stale()?) {
+                continue;
+            }
+            if file.is_excluded(&secret_keys) {
+                if file.is_stale()? {
+                    println!(
+                        "{}",
+                        yellow.apply_to(format!(
+                            "Cannot re-encrypt {file}, it has to be done by one of its recipients"
+                        ))
+                    );
+                }
+                continue;
+            }
+            files.push(file);
+        }
+        files.sort();
+        let secrets = match self.stale_only {
+            true => repository.secrets_stale()?,
+            false => !repository.secrets(&secret_keys)?.is_empty(),
+        };
+
+        if files.is_empty() && !secrets {
+            println!("All encrypted files are up to date");
+            return Ok(());
+        }
+
+        let bold = Style::new().bold();
+        println!(
+            "Re-encrypt files in repository {}",
+            bold.apply_to(&repository.directory().to_string_lossy())
+        );
+        for file_ref in &files {
+            println!("  {}", bold.apply_to(file_ref.to_string()));
+        }
+        if secrets {
+            println!("  {}", bold.apply_to("secrets"));
+        }
+
+        if !self.dry_run {
+            repository.reencrypt_files(&files, secrets, &secret_keys)?;
+            commit_changes(&config, &repository, "re-encrypt files")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use std::{error::Error, path::PathBuf};
+
+use clap::{Args, Subcommand};
+use console::Style;
+use dialoguer::{theme::ColorfulTheme, Confirm};
+use prettytable::{row, Table};
+
+use crate::{
+    config::ConfigurationHolder,
+    repository::{atomic::resolve_symlinks, Backup, DefaultEnvironment},
+};
+
+use super::common::absolute_path;
+
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    #[clap(help = "Only list backups of a specific file")]
+    pub target: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct FileArgs {
+    #[clap(help = "File to restore")]
+    pub target: PathBuf,
+    #[clap(short, long, help = "Backup to restore from (default: latest)")]
+    pub backup: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    #[clap(help = "Backup to restore")]
+    pub backup: String,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RestoreSubCommand {
+    #[clap(about = "List backups", alias = "ls")]
+    List(ListArgs),
+    #[clap(about = "Restore a single file from a backup")]
+    File(FileArgs),
+    #[clap(about = "Restore all files of a backup (i.e. roll back an apply)")]
+    Run(RunArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct RestoreCommand {
+    #[clap(subcommand)]
+    subcommand: RestoreSubCommand,
+}
+
+impl RestoreCommand {
+    pub fn run(&self, config: ConfigurationHolder) -> Result<(), Box<dyn Error>> {
+        match &self.subcommand {
+            RestoreSubCommand::List(args) => self.list(config, args),
+            RestoreSubCommand::File(args) => self.restore_file(config, args),
+            RestoreSubCommand::Run(args) => self.restore_run(config, args),
+        }
+    }
+
+    fn list(&self, config: ConfigurationHolder, args: &ListArgs) -> Result<(), Box<dyn Error>> {
+        let target = args.target.as_deref().map(absolute_path).transpose()?;
+        let mut table = Table::new();
+
+        table.add_row(row![b => "Backup", "Target", "Previous state"]);
+        for backup in Backup::<DefaultEnvironment>::list(&config.backups_dir)? {
+            for entry in &backup.descriptor.entries {
+                if target
+                    .as_ref()
+                    .is_some_and(|target| target != &entry.target)
+                {
+                    continue;
+                }
+                let state = match (&entry.content, &entry.permission, &entry.link) {
+                    (None, _, Some(link)) => format!("-> {}", link.to_string_lossy()),
+                    (None, _, None) => "missing".to_string(),
+                    (Some(_), Some(permission), _) => permission.clone(),
+                    (Some(_), None, _) => "".to_string(),
+                };
+
+                table.add_row(row![backup.id, entry.target.to_string_lossy(), state]);
+            }
+        }
+
+        table.printstd();
+
+        Ok(())
+    }
+
+    fn restore_file(
+        &self,
+        config: ConfigurationHolder,
+        args: &FileArgs,
+    ) -> Result<(), Box<dyn Error>> {
+        let backups = Backup::<DefaultEnvironment>::list(&config.backups_dir)?;
+        let mut target = absolute_path(&args.target)?;
+        // Apply writes through symlinks, so the backup may be of the file linked to
+        if !backups.iter().any(|backup| backup.entry(&target).is_some()) {
+            target = resolve_symlinks(&target)?;
+        }
+        let backup = backups
+            .iter()
+            .rev()
+            .filter(|backup| args.backup.iter().all(|id| &backup.id == id))
+            .find(|backup| backup.entry(&target).is_some())
+            .ok_or_else(|| format!("No backup found for {}", target.to_string_lossy()))?;
+        let entry = backup.entry(&target).ok_or("Backup entry missing")?;
+
+        if let Some(true) = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "Restore {} from backup {}",
+                target.to_string_lossy(),
+                backup.id
+            ))
+            .default(true)
+            .interact_opt()?
+        {
+            let mut current = Backup::new(&config.backups_dir);
+
+            backup.restore(entry, &mut current)?;
+            print_current(&current);
+        }
+
+        Ok(())
+    }
+
+    fn restore_run(
+        &self,
+        config: ConfigurationHolder,
+        args: &RunArgs,
+    ) -> Result<(), Box<dyn Error>> {
+        let backups = Backup::<DefaultEnvironment>::list(&config.backups_dir)?;
+        let backup = backups
+            .iter()
+            .find(|backup| backup.id == args.backup)
+            .ok_or_else(|| format!("No backup {} found", args.backup))?;
+
+        let bold = Style::new().bold();
+        println!("Restore backup {}", bold.apply_to(&backup.id));
+        for entry in &backup.descriptor.entries {
+            println!("  {}", bold.apply_to(entry.target.to_string_lossy()));
+        }
+        println!();
+
+        if let Some(true) = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Continue")
+            .default(true)
+            .interact_opt()?
+        {
+            let mut current = Backup::new(&config.backups_dir);
+
+            for entry in &backup.descriptor.entries {
+                backup.restore(entry, &mut current)?;
+            }
+            print_current(&current);
+        }
+
+        Ok(())
+    }
+}
+
+fn print_current(current: &Backup<DefaultEnvironment>) {
+    if !current.is_empty() {
+        println!(
+            "Replaced state saved in backup {}",
+            Style::new().bold().apply_to(&current.id)
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::{error::Error, path::PathBuf};
+
+use clap::{Args, Subcommand};
+use console::Style;
+use dialoguer::Password;
+
+use crate::{
+    config::ConfigurationHolder,
+    repository::{DefaultEnvironment, Repository},
+};
+
+use super::common::{commit_changes, require_secret_keys};
+
+#[derive(Debug, Args)]
+pub struct SetArgs {
+    #[clap(help = "Name of the secret")]
+    pub key: String,
+    #[clap(help = "Value of the secret (prompted for if missing)")]
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct KeyArgs {
+    #[clap(help = "Name of the secret")]
+    pub key: String,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SecretsSubCommand {
+    #[clap(about = "Set a secret")]
+    Set(SetArgs),
+    #[clap(about = "Print the value of a secret")]
+    Get(KeyArgs),
+    #[clap(about = "List the names of all secrets", alias = "ls")]
+    List,
+    #[clap(about = "Remove a secret", alias = "remove")]
+    Rm(KeyArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct SecretsCommand {
+    #[clap(subcommand)]
+    subcommand: SecretsSubCommand,
+}
+
+impl SecretsCommand {
+    pub fn run(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let repository = Repository::<DefaultEnvironment>::open(&repository_path)?;
+        let secret_keys = require_secret_keys(&config)?;
+        let mut secrets = repository.secrets(&secret_keys)?;
+
+        match &self.subcommand {
+            SecretsSubCommand::Set(args) => {
+                let value = match &args.value {
+                    Some(value) => value.clone(),
+                    None => Password::new()
+                        .with_prompt(format!("Value of {}", args.key))
+                        .allow_empty_password(true)
+                        .interact()?,
+                };
+
+                secrets.insert(args.key.clone(), value);
+                repository.store_secrets(&secrets)?;
+                commit_changes(&config, &repository, &format!("secrets: set {}", args.key))
+            }
+            SecretsSubCommand::Get(args) => {
+                let value = secrets
+                    .get(&args.key)
+                    .ok_or_else(|| format!("No secret {} found", args.key))?;
+
+                println!("{value}");
+                Ok(())
+            }
+            SecretsSubCommand::List => {
+                for key in secrets.keys() {
+                    println!("{}", Style::new().bold().apply_to(key));
+                }
+                Ok(())
+            }
+            SecretsSubCommand::Rm(args) => {
+                if secrets.remove(&args.key).is_none() {
+                    return Err(format!("No secret {} found", args.key).into());
+                }
+                repository.store_secrets(&secrets)?;
+                commit_changes(&config, &repository, &format!("secrets: rm {}", args.key))
+            }
+        }
+    }
+}
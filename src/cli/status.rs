@@ -0,0 +1,161 @@
+use std::{error::Error, path::PathBuf};
+
+use clap::Args;
+use console::Style;
+use serde::Serialize;
+
+use crate::{
+    config::ConfigurationHolder,
+    model::{MachineContext, SecretKey, FORMAT_VERSION},
+    repository::{Changes, DefaultEnvironment, FileRef, Repository},
+};
+
+use super::{
+    common::{require_machine_context, require_secret_keys},
+    ReportedFailure,
+};
+
+#[derive(Debug, Args)]
+pub struct StatusCommand {
+    #[clap(long, help = "Print status as json")]
+    json: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+enum FileStatus {
+    Clean,
+    New,
+    Modified,
+    Permission { current: String, expected: String },
+    Unlinked { current: Option<PathBuf> },
+    NotApplicable,
+    Error { message: String },
+}
+
+#[derive(Debug, Serialize)]
+struct StatusEntry {
+    target: PathBuf,
+    source: String,
+    #[serde(flatten)]
+    status: FileStatus,
+}
+
+impl StatusCommand {
+    pub fn run(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let repository = Repository::<DefaultEnvironment>::open(repository_path)?;
+        let secret_keys = require_secret_keys(&config)?;
+        let context = require_machine_context(&config)?;
+
+        let mut entries = Vec::new();
+
+        for file in repository.files() {
+            if file.is_excluded(&secret_keys) {
+                continue;
+            }
+            let status = match file.is_applicable(&context) {
+                Ok(true) => file_status(&file, &context, &secret_keys),
+                Ok(false) => FileStatus::NotApplicable,
+                Err(error) => FileStatus::Error {
+                    message: error.to_string(),
+                },
+            };
+
+            entries.push(StatusEntry {
+                target: file.file.target.clone(),
+                source: file.to_string(),
+                status,
+            });
+        }
+        entries.sort_by(|a, b| a.target.cmp(&b.target));
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+        } else {
+            print_entries(&entries);
+        }
+        if repository.format_version() < FORMAT_VERSION {
+            eprintln!(
+                "{}",
+                Style::new().yellow().apply_to(format!(
+                    "Repository has format version {}, use 'dotium migrate' to upgrade it",
+                    repository.format_version()
+                ))
+            );
+        }
+
+        if entries
+            .iter()
+            .any(|entry| !matches!(entry.status, FileStatus::Clean | FileStatus::NotApplicable))
+        {
+            return Err(ReportedFailure.into());
+        }
+
+        Ok(())
+    }
+}
+
+fn file_status(
+    file: &FileRef<DefaultEnvironment>,
+    context: &MachineContext,
+    secret_keys: &[SecretKey],
+) -> FileStatus {
+    match file.outcome(context, secret_keys) {
+        Ok(outcome) => match outcome.changes() {
+            Ok(Changes::None) => FileStatus::Clean,
+            Ok(Changes::NewFile) => FileStatus::New,
+            Ok(Changes::Diff(_)) => FileStatus::Modified,
+            Ok(Changes::ChangePermission(current)) => FileStatus::Permission {
+                current,
+                expected: outcome.permission.clone(),
+            },
+            Ok(Changes::Relink(current)) => FileStatus::Unlinked { current },
+            Err(error) => FileStatus::Error {
+                message: error.to_string(),
+            },
+        },
+        Err(outcome_error) => FileStatus::Error {
+            message: outcome_error.error.to_string(),
+        },
+    }
+}
+
+fn print_entries(entries: &[StatusEntry]) {
+    let green = Style::new().green();
+    let yellow = Style::new().yellow();
+    let cyan = Style::new().cyan();
+    let red = Style::new().red();
+
+    for entry in entries {
+        let target = entry.target.to_string_lossy();
+
+        match &entry.status {
+            FileStatus::Clean => println!("  clean:       {target}"),
+            FileStatus::New => println!("{}", green.apply_to(format!("  new:         {target}"))),
+            FileStatus::Modified => {
+                println!("{}", yellow.apply_to(format!("  modified:    {target}")))
+            }
+            FileStatus::Permission { current, expected } => println!(
+                "{}",
+                cyan.apply_to(format!("  permission:  {target} ({current} -> {expected})"))
+            ),
+            FileStatus::Unlinked { current } => println!(
+                "{}",
+                cyan.apply_to(match current {
+                    Some(current) =>
+                        format!("  unlinked:    {target} (-> {})", current.to_string_lossy()),
+                    None => format!("  unlinked:    {target} (regular file)"),
+                })
+            ),
+            FileStatus::NotApplicable => println!("  n/a:         {target}"),
+            FileStatus::Error { message } => println!(
+                "{}",
+                red.apply_to(format!("  error:       {target} ({message})"))
+            ),
+        }
+    }
+}
@@ -9,20 +9,40 @@ use dialoguer::{theme::ColorfulTheme, Confirm};
 use std::fs;
 
 use crate::{
+    config::ConfigurationHolder,
     model::FileAction,
     repository::{DefaultEnvironment, Repository},
 };
 
+use super::common::{commit_changes, describe_targets};
+
 #[derive(Debug, Args)]
 pub struct TrackCommand {
     #[clap(help = "File or directory to add to repository")]
     file_or_directory: PathBuf,
     #[clap(short, long, value_enum, default_value = "as-is")]
     action: FileAction,
+    #[clap(
+        short,
+        long,
+        value_delimiter = ',',
+        help = "Restrict encrypted files to these recipients or groups"
+    )]
+    recipients: Vec<String>,
+    #[clap(
+        short,
+        long,
+        help = "Only apply on machines matching this condition, e.g. 'os == \"linux\"'"
+    )]
+    condition: Option<String>,
 }
 
 impl TrackCommand {
-    pub fn run(&self, repository_path: PathBuf) -> Result<(), Box<dyn Error>> {
+    pub fn run(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
         let mut repository = Repository::<DefaultEnvironment>::open(&repository_path)?;
 
         let mut targets = Vec::new();
@@ -39,7 +59,9 @@ impl TrackCommand {
             .into());
         }
 
-        let added = repository.add_files(self.action, targets)?;
+        let recipients = Some(self.recipients.clone()).filter(|r| !r.is_empty());
+        let added =
+            repository.add_files(self.action, recipients, self.condition.clone(), targets)?;
 
         let bold = Style::new().bold();
         println!(
@@ -59,6 +81,12 @@ impl TrackCommand {
                 file_ref.file.action
             );
         }
+        if !self.recipients.is_empty() {
+            println!("Restricted to {}", self.recipients.join(", "));
+        }
+        if let Some(condition) = &self.condition {
+            println!("Only applied if {condition}");
+        }
 
         println!();
 
@@ -68,6 +96,27 @@ impl TrackCommand {
             .interact_opt()?
         {
             repository.store()?;
+
+            // Content is in the repository now, so the target can be replaced by a link
+            for file_ref in &added {
+                if file_ref.file.action == FileAction::Symlink {
+                    file_ref.link_target()?;
+                }
+            }
+
+            let targets = added
+                .iter()
+                .map(|file_ref| &file_ref.file.target)
+                .collect::<Vec<_>>();
+            commit_changes(
+                &config,
+                &repository,
+                &format!(
+                    "track {} ({})",
+                    describe_targets(&targets),
+                    self.action.name()
+                ),
+            )?;
         } else {
             for file_ref in added {
                 fs::remove_file(file_ref.absolute_source()).ok();
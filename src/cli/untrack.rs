@@ -0,0 +1,118 @@
+use std::{error::Error, fs, path::PathBuf};
+
+use clap::Args;
+use console::Style;
+use dialoguer::{theme::ColorfulTheme, Confirm};
+
+use crate::{
+    config::ConfigurationHolder,
+    model::FileAction,
+    repository::{DefaultEnvironment, FileRef, Repository},
+};
+
+use super::common::{absolute_path, commit_changes, describe_targets};
+
+#[derive(Debug, Args)]
+pub struct UntrackCommand {
+    #[clap(help = "File or directory to remove from repository")]
+    file_or_directory: PathBuf,
+    #[clap(long, help = "Also delete the file(s) from the current config")]
+    delete_target: bool,
+}
+
+impl UntrackCommand {
+    pub fn run(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut repository = Repository::<DefaultEnvironment>::open(&repository_path)?;
+        let path = absolute_path(&self.file_or_directory)?;
+
+        let mut files = Vec::new();
+        for file in repository.files() {
+            if file.absolute_target()?.starts_with(&path) {
+                files.push(file);
+            }
+        }
+        if files.is_empty() {
+            return Err(format!(
+                "No repository file found for {}",
+                self.file_or_directory.to_string_lossy()
+            )
+            .into());
+        }
+        files.sort();
+
+        let bold = Style::new().bold();
+        println!(
+            "Remove files from repository {}",
+            bold.apply_to(&repository.directory().to_string_lossy())
+        );
+        for file_ref in &files {
+            println!(
+                "  {} -> {} ({:?})",
+                bold.apply_to(file_ref.file.target.to_string_lossy()),
+                bold.apply_to(file_ref.to_string()),
+                file_ref.file.action
+            );
+        }
+        if self.delete_target {
+            println!();
+            println!(
+                "{}",
+                Style::new()
+                    .red()
+                    .apply_to("Files will be deleted from the current config as well")
+            );
+        }
+
+        println!();
+
+        if let Some(true) = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Continue")
+            .default(!self.delete_target)
+            .interact_opt()?
+        {
+            if !self.delete_target {
+                // Keep the content of symlinked files, the source is about to be deleted
+                for file_ref in &files {
+                    if file_ref.file.action == FileAction::Symlink {
+                        file_ref.unlink_target()?;
+                    }
+                }
+            }
+
+            repository.remove_files(&files)?;
+            repository.store()?;
+
+            let targets = files
+                .iter()
+                .map(|file_ref| &file_ref.file.target)
+                .collect::<Vec<_>>();
+            commit_changes(
+                &config,
+                &repository,
+                &format!("untrack {}", describe_targets(&targets)),
+            )?;
+
+            if self.delete_target {
+                delete_targets(&files)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn delete_targets(files: &[FileRef<DefaultEnvironment>]) -> Result<(), Box<dyn Error>> {
+    for file_ref in files {
+        let target = file_ref.absolute_target()?;
+
+        if fs::symlink_metadata(&target).is_ok() {
+            fs::remove_file(target)?;
+        }
+    }
+
+    Ok(())
+}
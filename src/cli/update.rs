@@ -1,21 +1,33 @@
-use std::{error::Error, fs, path::PathBuf};
+use std::{error::Error, fs, path::PathBuf, str};
 
 use clap::Args;
-use dialoguer::{theme::ColorfulTheme, FuzzySelect};
+use console::Style;
+use dialoguer::{theme::ColorfulTheme, Editor, FuzzySelect};
 
 use crate::{
     cli::common::show_color_diff,
     config::ConfigurationHolder,
-    model::FileAction,
+    model::{MachineContext, SecretKey},
     repository::{DefaultEnvironment, Environment, FileRef, Repository},
+    utils::{template_patch::patch_template, unified_diff::UnifiedDiff},
 };
 
-use super::common::require_secret_keys;
+use super::common::{
+    absolute_path, commit_changes, describe_targets, require_machine_context, require_secret_keys,
+};
 
 #[derive(Debug, Args)]
 pub struct UpdateCommand {
-    #[clap(help = "File or directory to add to repository")]
-    file_or_directory: PathBuf,
+    #[clap(
+        required_unless_present = "all",
+        conflicts_with = "all",
+        help = "File or directory to update in repository"
+    )]
+    file_or_directory: Option<PathBuf>,
+    #[clap(short, long, help = "Check all tracked files for local changes")]
+    all: bool,
+    #[clap(short, long, help = "Update all changed files without asking")]
+    yes: bool,
 }
 
 impl UpdateCommand {
@@ -26,62 +38,266 @@ impl UpdateCommand {
     ) -> Result<(), Box<dyn Error>> {
         let repository = Repository::<DefaultEnvironment>::open(repository_path)?;
         let secret_keys = require_secret_keys(&config)?;
+        let context = require_machine_context(&config)?;
+        let filter = self
+            .file_or_directory
+            .as_deref()
+            .map(absolute_path)
+            .transpose()?;
 
+        let mut files = Vec::new();
         for file in repository.files() {
-            if self.file_or_directory == file.absolute_target()? {
-                if file.file.action == FileAction::J2 {
-                    return Err(format!(
-                        "Cannot update j2 content; {}",
-                        &self.file_or_directory.to_string_lossy()
-                    )
-                    .into());
+            let target = file.absolute_target()?;
+
+            if filter.iter().all(|filter| target.starts_with(filter)) {
+                files.push((target, file));
+            }
+        }
+        if let (Some(filter), true) = (&filter, files.is_empty()) {
+            return Err(
+                format!("No repository file found for {}", filter.to_string_lossy()).into(),
+            );
+        }
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut changed = 0;
+        let mut updated = Vec::new();
+        for (target, file) in files {
+            if file.is_excluded(&secret_keys) {
+                continue;
+            }
+            match file.is_applicable(&context) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(error) if filter.as_ref() == Some(&target) => return Err(error),
+                Err(error) => {
+                    eprintln!(
+                        "{}: Unable to update {} due to '{}'",
+                        Style::new().red().apply_to("Error"),
+                        file.file.target.to_string_lossy(),
+                        error
+                    );
+                    continue;
+                }
+            }
+            if !target.is_file() {
+                if filter.as_ref() == Some(&target) {
+                    return Err(format!("{} does not exist", target.to_string_lossy()).into());
                 }
-                let repository_content = file.get_content(&secret_keys)?;
-                let current_content = fs::read(&self.file_or_directory)?;
+                continue;
+            }
+            let current_content = fs::read(&target)?;
+
+            let has_changes = if file.file.action.is_template() {
+                self.update_template(&file, &context, &secret_keys, &current_content)?
+            } else {
+                self.update_file(&file, &secret_keys, &current_content)?
+            };
+            if has_changes {
+                changed += 1;
+            }
+            if repository
+                .changed_files()
+                .contains(&file.dir_path.join(&file.file.source))
+            {
+                updated.push(file.file.target.clone());
+            }
+        }
+
+        if changed == 0 {
+            println!("No local changes");
+        }
+        if !updated.is_empty() {
+            commit_changes(
+                &config,
+                &repository,
+                &format!("update {}", describe_targets(&updated)),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the local file differs from the repository.
+    fn update_file<E: Environment>(
+        &self,
+        file_ref: &FileRef<E>,
+        secret_keys: &[SecretKey],
+        current_content: &[u8],
+    ) -> Result<bool, Box<dyn Error>> {
+        let repository_content = file_ref.get_content(secret_keys)?;
+
+        if repository_content == current_content {
+            return Ok(false);
+        }
+        if self.yes {
+            println!("Update {}", file_ref.file.target.to_string_lossy());
+            file_ref.set_content(current_content)?;
+            return Ok(true);
+        }
 
-                if repository_content == current_content {
-                    println!("No diff {}", &self.file_or_directory.to_string_lossy());
-                    return Ok(());
+        loop {
+            match FuzzySelect::with_theme(&ColorfulTheme::default())
+                .items(&["Yes", "Show details", "No"])
+                .with_prompt(format!(
+                    "Update file {}",
+                    file_ref.file.target.to_string_lossy()
+                ))
+                .default(0)
+                .interact_opt()?
+            {
+                Some(0) => {
+                    file_ref.set_content(current_content)?;
+                    return Ok(true);
                 }
+                Some(1) => {
+                    println!();
+                    println!("{}", file_ref.file.target.to_string_lossy());
+                    println!("-------------------------------------------------------------------------------");
+                    show_color_diff(&repository_content, current_content);
+                    println!("-------------------------------------------------------------------------------");
+                }
+                Some(2) => return Ok(true),
+                None => return Err("Aborted by user".into()),
+                _ => (),
+            }
+        }
+    }
+
+    /// Bring local changes of a rendered template back to the template.
+    ///
+    /// Changes outside of tags and blocks can be applied automatically, as long as the
+    /// patched template renders to the local file. Otherwise the template has to be edited.
+    fn update_template<E: Environment>(
+        &self,
+        file_ref: &FileRef<E>,
+        context: &MachineContext,
+        secret_keys: &[SecretKey],
+        current_content: &[u8],
+    ) -> Result<bool, Box<dyn Error>> {
+        let rendered = file_ref.get_rendered(context, secret_keys)?;
 
-                return update_diff(&file, &repository_content, &current_content);
+        if rendered == current_content {
+            return Ok(false);
+        }
+        let template = String::from_utf8(file_ref.get_content(secret_keys)?)?;
+        let rendered = String::from_utf8(rendered)?;
+        let current = str::from_utf8(current_content)?;
+
+        let patched = patch_template(&template, &rendered, current).filter(|patched| {
+            file_ref
+                .render(patched.as_bytes(), context, secret_keys)
+                .is_ok_and(|result| result == current_content)
+        });
+
+        if self.yes {
+            match &patched {
+                Some(patched) => {
+                    println!("Update template of {}", file_ref.file.target.to_string_lossy());
+                    file_ref.set_content(patched.as_bytes())?;
+                }
+                None => println!(
+                    "{}",
+                    Style::new().yellow().apply_to(format!(
+                        "Cannot update template of {} automatically, use 'dotium update' without --yes",
+                        file_ref.file.target.to_string_lossy()
+                    ))
+                ),
             }
+            return Ok(true);
+        }
+
+        let mut items = vec![];
+        if patched.is_some() {
+            items.push("Apply changes to template");
         }
+        items.extend(["Edit template", "Show details", "No"]);
 
-        Err(format!(
-            "No repository file found for {}",
-            &self.file_or_directory.to_string_lossy()
-        )
-        .into())
+        loop {
+            let selection = FuzzySelect::with_theme(&ColorfulTheme::default())
+                .items(&items)
+                .with_prompt(format!(
+                    "Update template of {}",
+                    file_ref.file.target.to_string_lossy()
+                ))
+                .default(0)
+                .interact_opt()?;
+
+            match selection.map(|index| items[index]) {
+                Some("Apply changes to template") => {
+                    if let Some(patched) = &patched {
+                        file_ref.set_content(patched.as_bytes())?;
+                        return Ok(true);
+                    }
+                }
+                Some("Edit template") => {
+                    edit_template(
+                        file_ref,
+                        context,
+                        secret_keys,
+                        &template,
+                        &rendered,
+                        current,
+                    )?;
+                    return Ok(true);
+                }
+                Some("Show details") => {
+                    println!();
+                    println!("{}", file_ref.file.target.to_string_lossy());
+                    println!("-------------------------------------------------------------------------------");
+                    show_color_diff(rendered.as_bytes(), current_content);
+                    println!("-------------------------------------------------------------------------------");
+                    if patched.is_none() {
+                        println!(
+                            "{}",
+                            Style::new().yellow().apply_to(
+                                "Changes affect template expressions or blocks, they cannot be applied automatically"
+                            )
+                        );
+                    }
+                }
+                Some(_) => return Ok(true),
+                None => return Err("Aborted by user".into()),
+            }
+        }
     }
 }
 
-fn update_diff<E: Environment>(
+/// Open the template in the editor, with the local changes on top as a comment.
+fn edit_template<E: Environment>(
     file_ref: &FileRef<E>,
-    repository_content: &[u8],
-    current_content: &[u8],
+    context: &MachineContext,
+    secret_keys: &[SecretKey],
+    template: &str,
+    rendered: &str,
+    current: &str,
 ) -> Result<(), Box<dyn Error>> {
-    loop {
-        match FuzzySelect::with_theme(&ColorfulTheme::default())
-            .items(&["Yes", "Show details", "No"])
-            .with_prompt(format!(
-                "Update file {}",
-                file_ref.file.target.to_string_lossy()
-            ))
-            .default(0)
-            .interact_opt()?
-        {
-            Some(0) => return file_ref.set_content(current_content),
-            Some(1) => {
-                println!();
-                println!("{}", file_ref.file.target.to_string_lossy());
-                println!("-------------------------------------------------------------------------------");
-                show_color_diff(repository_content, current_content);
-                println!("-------------------------------------------------------------------------------");
-            }
-            Some(2) => return Ok(()),
-            None => return Err("Aborted by user".into()),
-            _ => (),
+    let changes = UnifiedDiff::new("rendered", "local", rendered, current).to_string();
+    let header = format!(
+        "{{#- dotium: local changes of {} (this comment is removed on save)\n{}-#}}\n",
+        file_ref.file.target.to_string_lossy(),
+        changes.replace("#}", "# }")
+    );
+
+    if let Some(edited) = Editor::new()
+        .trim_newlines(false)
+        .edit(&format!("{header}{template}"))?
+    {
+        let edited = edited.strip_prefix(&header).unwrap_or(&edited);
+
+        file_ref.set_content(edited.as_bytes())?;
+
+        if file_ref.get_rendered(context, secret_keys)? == current.as_bytes() {
+            println!("Template renders to the local file now");
+        } else {
+            println!(
+                "{}",
+                Style::new()
+                    .yellow()
+                    .apply_to("Rendered template still differs from the local file")
+            );
         }
     }
+
+    Ok(())
 }
@@ -1,11 +1,14 @@
-use std::error::Error;
+use std::{error::Error, path::PathBuf};
 
 use clap::{Args, Subcommand};
 use prettytable::{row, Table};
 
-use crate::config::ConfigurationHolder;
+use crate::{
+    config::ConfigurationHolder,
+    repository::{DefaultEnvironment, Environment, Repository},
+};
 
-use super::common::require_self;
+use super::common::{commit_changes, require_self};
 
 #[derive(Debug, Args)]
 pub struct SetMachineArgs {
@@ -13,10 +16,29 @@ pub struct SetMachineArgs {
     pub value: String,
 }
 
+#[derive(Debug, Args)]
+pub struct SetRepositoryArgs {
+    pub key: String,
+    pub value: String,
+    #[clap(short, long, help = "Only set the variable for this recipient")]
+    pub recipient: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct UnsetRepositoryArgs {
+    pub key: String,
+    #[clap(short, long, help = "Only unset the variable of this recipient")]
+    pub recipient: Option<String>,
+}
+
 #[derive(Debug, Subcommand)]
 pub enum VariablesSubCommand {
     #[clap(about = "Set machine variable")]
     SetMachine(SetMachineArgs),
+    #[clap(about = "Set repository variable shared by all machines")]
+    SetRepository(SetRepositoryArgs),
+    #[clap(about = "Remove repository variable")]
+    UnsetRepository(UnsetRepositoryArgs),
     #[clap(about = "Show all configured variables")]
     Show,
 }
@@ -28,10 +50,20 @@ pub struct VariablesCommand {
 }
 
 impl VariablesCommand {
-    pub fn run(&self, config: ConfigurationHolder) -> Result<(), Box<dyn Error>> {
+    pub fn run(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
         match self.subcommand {
             VariablesSubCommand::SetMachine(ref args) => self.set_machine(config, args),
-            VariablesSubCommand::Show => self.show(config),
+            VariablesSubCommand::SetRepository(ref args) => {
+                self.set_repository(config, repository_path, args)
+            }
+            VariablesSubCommand::UnsetRepository(ref args) => {
+                self.unset_repository(config, repository_path, args)
+            }
+            VariablesSubCommand::Show => self.show(config, repository_path),
         }
     }
 
@@ -52,18 +84,109 @@ impl VariablesCommand {
         config.store()
     }
 
-    fn show(&self, config: ConfigurationHolder) -> Result<(), Box<dyn Error>> {
+    fn set_repository(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+        args: &SetRepositoryArgs,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut repository = Repository::<DefaultEnvironment>::open(&repository_path)?;
+
+        if let Some(recipient) = &args.recipient {
+            if !repository.recipients().any(|r| &r.name == recipient) {
+                return Err(format!("No recipient {recipient} found").into());
+            }
+        }
+        repository.set_variable(args.recipient.as_deref(), &args.key, &args.value);
+
+        repository.store()?;
+        commit_changes(
+            &config,
+            &repository,
+            &format!("variables: set {}", args.key),
+        )
+    }
+
+    fn unset_repository(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+        args: &UnsetRepositoryArgs,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut repository = Repository::<DefaultEnvironment>::open(&repository_path)?;
+
+        if !repository.unset_variable(args.recipient.as_deref(), &args.key) {
+            return Err(format!("Repository variable {} not set", args.key).into());
+        }
+
+        repository.store()?;
+        commit_changes(
+            &config,
+            &repository,
+            &format!("variables: unset {}", args.key),
+        )
+    }
+
+    fn show(
+        &self,
+        config: ConfigurationHolder,
+        repository_path: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
         let recipient = require_self(&config)?;
         let mut table = Table::new();
 
-        table.add_row(row!["recipient", recipient.name]);
+        table.add_row(row![b => "Variable", "Value", "Layer"]);
+        table.add_row(row!["recipient", recipient.name, ""]);
+
+        let mut system = Vec::new();
+        flatten_value(
+            "system",
+            &serde_json::to_value(DefaultEnvironment::system()?)?,
+            &mut system,
+        );
+        for (key, value) in system {
+            table.add_row(row![key, value, "system"]);
+        }
+
+        // Later layers take precedence, overridden values are not shown
+        let mut variables = Vec::new();
+        if repository_path.join("dotium.json").is_file() {
+            let repository = Repository::<DefaultEnvironment>::open(&repository_path)?;
+            let repository_variables = repository.variables();
 
+            for (key, value) in &repository_variables.shared {
+                variables.push((key.clone(), value.clone(), "repository".to_string()));
+            }
+            for (key, value) in repository_variables
+                .recipients
+                .get(&recipient.name)
+                .into_iter()
+                .flatten()
+            {
+                variables.push((
+                    key.clone(),
+                    value.clone(),
+                    format!("repository ({})", recipient.name),
+                ));
+            }
+        }
         for (key, value) in config
             .configuration
             .map(|c| c.variables)
             .unwrap_or_default()
         {
-            table.add_row(row![format!("machine.{}", key), value]);
+            variables.push((key, value, "machine".to_string()));
+        }
+
+        let mut effective: Vec<(String, String, String)> = Vec::new();
+        for variable in variables {
+            effective.retain(|(key, _, _)| key != &variable.0);
+            effective.push(variable);
+        }
+        effective.sort();
+
+        for (key, value, layer) in effective {
+            table.add_row(row![format!("variables.{key}"), value, layer]);
         }
 
         table.printstd();
@@ -71,3 +194,16 @@ impl VariablesCommand {
         Ok(())
     }
 }
+
+fn flatten_value(prefix: &str, value: &serde_json::Value, result: &mut Vec<(String, String)>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                flatten_value(&format!("{prefix}.{key}"), value, result);
+            }
+        }
+        serde_json::Value::String(value) => result.push((prefix.to_string(), value.clone())),
+        serde_json::Value::Null => result.push((prefix.to_string(), "".to_string())),
+        value => result.push((prefix.to_string(), value.to_string())),
+    }
+}
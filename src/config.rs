@@ -1,10 +1,19 @@
-use std::{collections::HashMap, error::Error, fs, path::PathBuf};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
 
+use age::secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    model::{Recipient, SecretKey},
-    repository::Environment,
+    model::{
+        is_protected, protect_keys, request_new_passphrase, request_passphrase, unprotect_keys,
+        Recipient, SecretKey,
+    },
+    repository::{atomic, Environment},
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -12,12 +21,19 @@ pub struct Configuration {
     pub default_recipient: Recipient,
     #[serde(default)]
     pub variables: HashMap<String, String>,
+    /// Additional identities (e.g. SSH private keys) besides the keys file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub identity_files: Vec<PathBuf>,
+    /// Commit changes to the repository with git and check its working copy before apply
+    #[serde(default)]
+    pub git: bool,
 }
 
 #[derive(Debug)]
 pub struct ConfigurationHolder {
     pub config_file: PathBuf,
     pub keys_file: PathBuf,
+    pub backups_dir: PathBuf,
     pub configuration: Option<Configuration>,
 }
 
@@ -35,6 +51,8 @@ impl ConfigurationHolder {
             None => E::config_dir()?.join("keys.txt"),
         };
 
+        let backups_dir = E::config_dir()?.join("backups");
+
         let configuration = if config_file.is_file() {
             let mut file = fs::File::open(&config_file)?;
 
@@ -46,11 +64,17 @@ impl ConfigurationHolder {
         Ok(ConfigurationHolder {
             config_file,
             keys_file,
+            backups_dir,
             configuration,
         })
     }
 
-    pub fn init(&self, hostname: &str) -> Result<(), Box<dyn Error>> {
+    pub fn init(
+        &self,
+        hostname: &str,
+        ssh_key: Option<&Path>,
+        protect: bool,
+    ) -> Result<(), Box<dyn Error>> {
         if let Some(parent) = self.config_file.parent() {
             fs::create_dir_all(parent)?;
         }
@@ -58,11 +82,44 @@ impl ConfigurationHolder {
             fs::create_dir_all(parent)?;
         }
 
-        let sk = SecretKey::generate();
-        let default_recipient = sk.as_recipient(hostname);
-        let configuration = Configuration {
-            default_recipient,
-            variables: HashMap::new(),
+        let configuration = match ssh_key {
+            Some(ssh_key) => Configuration {
+                default_recipient: SecretKey::ssh_recipient(
+                    fs::File::open(ssh_key)?,
+                    Some(ssh_key.to_string_lossy().to_string()),
+                    hostname,
+                )?,
+                variables: HashMap::new(),
+                identity_files: vec![ssh_key.to_path_buf()],
+                git: false,
+            },
+            None => {
+                let sk = SecretKey::generate();
+
+                if protect {
+                    if self.keys_file.exists() {
+                        return Err(
+                            "Keys file already exists, use 'dotium keys protect' instead".into(),
+                        );
+                    }
+                    let mut keys = Vec::new();
+                    sk.write_to(&mut keys)?;
+
+                    self.store_keys(&protect_keys(
+                        &keys,
+                        request_new_passphrase("Passphrase for keys file")?,
+                    )?)?;
+                } else {
+                    self.add_key(&sk)?;
+                }
+
+                Configuration {
+                    default_recipient: sk.as_recipient(hostname),
+                    variables: HashMap::new(),
+                    identity_files: vec![],
+                    git: false,
+                }
+            }
         };
         let mut config_file = fs::OpenOptions::new()
             .write(true)
@@ -70,15 +127,57 @@ impl ConfigurationHolder {
             .open(&self.config_file)?;
         serde_json::to_writer_pretty(&mut config_file, &configuration)?;
 
-        let key_file = fs::OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(&self.keys_file)?;
-        sk.write_to(&key_file)?;
-
         Ok(())
     }
 
+    /// Replace the content of the keys file, which is only readable by the owner.
+    ///
+    /// The file is replaced atomically, so a failed write never loses the identities.
+    /// Add a key to the keys file, a protected keys file stays protected by its passphrase.
+    fn add_key(&self, secret_key: &SecretKey) -> Result<(), Box<dyn Error>> {
+        let mut keys = match self.keys_file.is_file() {
+            true => fs::read(&self.keys_file)?,
+            false => Vec::new(),
+        };
+        let passphrase = match is_protected(&keys) {
+            true => {
+                let passphrase = request_passphrase(&format!(
+                    "Passphrase for {}",
+                    self.keys_file.to_string_lossy()
+                ))?;
+                keys = unprotect_keys(
+                    &keys,
+                    SecretString::from(passphrase.expose_secret().to_string()),
+                )?;
+                Some(passphrase)
+            }
+            false => None,
+        };
+
+        if !keys.is_empty() && !keys.ends_with(b"\n") {
+            keys.push(b'\n');
+        }
+        secret_key.write_to(&mut keys)?;
+
+        match passphrase {
+            Some(passphrase) => self.store_keys(&protect_keys(&keys, passphrase)?),
+            None => self.store_keys(&keys),
+        }
+    }
+
+    pub fn store_keys(&self, content: &[u8]) -> Result<(), Box<dyn Error>> {
+        #[cfg(unix)]
+        let permissions = {
+            use std::os::unix::fs::PermissionsExt;
+
+            Some(fs::Permissions::from_mode(0o600))
+        };
+        #[cfg(not(unix))]
+        let permissions = None;
+
+        atomic::write_file(&self.keys_file, content, permissions)
+    }
+
     pub fn store(&self) -> Result<(), Box<dyn Error>> {
         if let Some(configuration) = &self.configuration {
             let mut config_file = fs::OpenOptions::new()
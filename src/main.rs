@@ -44,6 +44,9 @@ fn main() {
         };
 
     if let Err(err) = opts.sub_command.run(config, opts.repository) {
+        if err.is::<cli::ReportedFailure>() {
+            std::process::exit(1);
+        }
         exit_on_error(err);
     }
 }
@@ -1,4 +1,9 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use serde::Serialize;
 
 use super::Recipient;
 
@@ -8,7 +13,61 @@ pub struct MachineContext {
     pub variables: HashMap<String, String>,
 }
 
+/// Facts about the machine and user, available to templates as `system`
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemContext {
+    pub hostname: String,
+    /// Operating system, e.g. `linux`, `macos` or `windows`
+    pub os: String,
+    /// Linux distribution, e.g. `ubuntu` or `arch` (`ID` of `/etc/os-release`)
+    pub distribution: Option<String>,
+    pub arch: String,
+    /// Left out if unknown, so templates using it fail instead of rendering an empty name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    pub home: PathBuf,
+    pub xdg: XdgDirs,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct XdgDirs {
+    pub config_home: Option<PathBuf>,
+    pub data_home: Option<PathBuf>,
+    pub cache_home: Option<PathBuf>,
+    pub state_home: Option<PathBuf>,
+}
+
+/// Locations of the file being rendered, available to templates as `file`
+#[derive(Debug, Clone, Serialize)]
+pub struct FilePaths {
+    /// Absolute path of the target
+    pub target: PathBuf,
+    /// Absolute path of the source in the repository
+    pub source: PathBuf,
+    /// Directory of the repository
+    pub repository: PathBuf,
+}
+
 #[derive(Debug, Clone)]
 pub struct FileContext<'a> {
     pub machine: &'a MachineContext,
+    pub system: &'a SystemContext,
+    pub file: FilePaths,
+    /// Repository variables of the machine's recipient
+    pub repository: BTreeMap<String, String>,
+    /// Decrypted secret variables, only available to templates
+    pub secrets: Option<&'a BTreeMap<String, String>>,
+}
+
+impl FileContext<'_> {
+    /// Repository variables merged with the machine variables, the latter take precedence.
+    pub fn variables(&self) -> BTreeMap<String, String> {
+        let mut variables = self.repository.clone();
+
+        variables.extend(self.machine.variables.clone());
+
+        variables
+    }
 }
@@ -10,7 +10,17 @@ pub struct Recipient {
 
 impl Recipient {
     pub fn to_age(&self) -> Result<Box<dyn age::Recipient>, Box<dyn Error>> {
-        Ok(age::x25519::Recipient::from_str(&self.key).map(Box::new)?)
+        if self.is_ssh() {
+            Ok(age::ssh::Recipient::from_str(&self.key)
+                .map(Box::new)
+                .map_err(|err| format!("Invalid SSH key of {}: {:?}", self.name, err))?)
+        } else {
+            Ok(age::x25519::Recipient::from_str(&self.key).map(Box::new)?)
+        }
+    }
+
+    pub fn is_ssh(&self) -> bool {
+        self.key.starts_with("ssh-")
     }
 }
 
@@ -1,8 +1,10 @@
+mod backup;
 mod context;
 mod key;
 mod repo;
 mod secret_key;
 
+pub use backup::*;
 pub use context::*;
 pub use key::*;
 pub use repo::*;
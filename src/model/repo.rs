@@ -1,18 +1,56 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, path::PathBuf};
 
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
 use super::Recipient;
 
+/// Current layout version of repositories, see `dotium migrate` for older ones
+pub const FORMAT_VERSION: u32 = 1;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RootDescriptor {
+    /// Layout version of the repository (repositories without one are version 0)
+    #[serde(default)]
+    pub format_version: u32,
     #[serde(default)]
     pub recipients: Vec<Recipient>,
     #[serde(default)]
     pub recipient_requests: Vec<Recipient>,
     #[serde(default)]
     pub directories: Vec<PathBuf>,
+    /// Named groups of recipient names, usable to restrict encrypted files
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "RepositoryVariables::is_empty")]
+    pub variables: RepositoryVariables,
+}
+
+/// Template variables shared by all machines using a repository.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RepositoryVariables {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub shared: BTreeMap<String, String>,
+    /// Variables of single recipients (by name), taking precedence over the shared ones
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub recipients: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl RepositoryVariables {
+    pub fn is_empty(&self) -> bool {
+        self.shared.is_empty() && self.recipients.is_empty()
+    }
+
+    /// Shared variables merged with the ones of a recipient.
+    pub fn for_recipient(&self, name: &str) -> BTreeMap<String, String> {
+        let mut variables = self.shared.clone();
+
+        if let Some(recipient_variables) = self.recipients.get(name) {
+            variables.extend(recipient_variables.clone());
+        }
+
+        variables
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,6 +66,12 @@ pub struct FileDescriptor {
     pub action: FileAction,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub permission: Option<String>,
+    /// Recipient names or groups an encrypted file is restricted to (default: all recipients)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipients: Option<Vec<String>>,
+    /// Tera expression deciding on which machines the file is applied (default: all)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ValueEnum)]
@@ -35,13 +79,31 @@ pub enum FileAction {
     AsIs,
     Crypted,
     J2,
+    Symlink,
+    /// Template that is stored encrypted
+    CryptedJ2,
 }
 
 impl FileAction {
+    pub fn is_crypted(&self) -> bool {
+        matches!(self, FileAction::Crypted | FileAction::CryptedJ2)
+    }
+
+    pub fn is_template(&self) -> bool {
+        matches!(self, FileAction::J2 | FileAction::CryptedJ2)
+    }
+
+    /// Name as used on the command line, e.g. `crypted-j2`
+    pub fn name(&self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
     pub fn default_permission(&self) -> String {
         match self {
-            FileAction::AsIs | FileAction::J2 => "0644".to_string(),
-            FileAction::Crypted => "0600".to_string(),
+            FileAction::AsIs | FileAction::J2 | FileAction::Symlink => "0644".to_string(),
+            FileAction::Crypted | FileAction::CryptedJ2 => "0600".to_string(),
         }
     }
 }
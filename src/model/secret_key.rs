@@ -1,56 +1,258 @@
 use std::{
+    env,
     error::Error,
     io::{BufRead, BufReader, Read, Write},
+    iter,
+    process::{Command, Stdio},
     str::FromStr,
 };
 
-use age::{secrecy::ExposeSecret, x25519::Identity};
+use age::{
+    armor::{ArmoredReader, ArmoredWriter, Format},
+    scrypt,
+    secrecy::{ExposeSecret, SecretString},
+    ssh, x25519, Decryptor, Encryptor,
+};
+use dialoguer::Password;
 
 use crate::model::Recipient;
 
-pub struct SecretKey(Identity);
+/// Environment variable containing the passphrase of protected keys
+pub const PASSPHRASE_ENV: &str = "DOTIUM_PASSPHRASE";
+/// Environment variable containing a program that prints the passphrase of protected keys
+pub const ASKPASS_ENV: &str = "DOTIUM_ASKPASS";
+
+pub enum SecretKey {
+    X25519(x25519::Identity),
+    Ssh {
+        identity: ssh::Identity,
+        public_key: String,
+    },
+}
 
 impl SecretKey {
     pub fn generate() -> Self {
-        SecretKey(Identity::generate())
+        SecretKey::X25519(x25519::Identity::generate())
     }
 
     pub fn as_recipient(&self, name: &str) -> Recipient {
         Recipient {
             name: name.to_string(),
-            key: self.0.to_public().to_string(),
+            key: self.public_key(),
+        }
+    }
+
+    pub fn public_key(&self) -> String {
+        match self {
+            SecretKey::X25519(identity) => identity.to_public().to_string(),
+            SecretKey::Ssh { public_key, .. } => public_key.clone(),
         }
     }
 
     pub fn write_to<W: Write>(&self, mut out: W) -> Result<(), Box<dyn Error>> {
-        let pk = self.0.to_public();
+        let identity = match self {
+            SecretKey::X25519(identity) => identity,
+            SecretKey::Ssh { .. } => return Err("SSH keys cannot be written to a keys file".into()),
+        };
 
         writeln!(
             out,
             "# created: {}",
             chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
         )?;
-        writeln!(out, "# public key: {pk}")?;
-        writeln!(out, "{}", self.0.to_string().expose_secret())?;
+        writeln!(out, "# public key: {}", identity.to_public())?;
+        writeln!(out, "{}", identity.to_string().expose_secret())?;
 
         Ok(())
     }
 
-    pub fn read_from<R: Read>(reader: R) -> Result<Vec<SecretKey>, Box<dyn Error>> {
+    /// Read all keys from an age keys file or an (OpenSSH formatted) SSH private key.
+    ///
+    /// Both can be mixed, i.e. SSH private keys can be pasted into a keys file. A keys
+    /// file protected by a passphrase is decrypted first.
+    pub fn read_from<R: Read>(
+        mut reader: R,
+        filename: Option<String>,
+    ) -> Result<Vec<SecretKey>, Box<dyn Error>> {
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+
+        if is_protected(&content) {
+            let passphrase = request_passphrase(&format!(
+                "Passphrase for {}",
+                filename.as_deref().unwrap_or("keys file")
+            ))?;
+            content = unprotect_keys(&content, passphrase)?;
+        }
+
         let mut result = Vec::new();
+        let mut ssh_block: Option<String> = None;
 
-        for try_line in BufReader::new(reader).lines() {
+        for try_line in BufReader::new(content.as_slice()).lines() {
             let line = try_line?;
 
-            if !line.is_empty() && !line.starts_with('#') {
-                result.push(SecretKey(Identity::from_str(&line)?));
+            if let Some(block) = ssh_block.as_mut() {
+                block.push_str(&line);
+                block.push('\n');
+
+                if line.starts_with("-----END") {
+                    result.push(read_ssh_identity(block, filename.clone())?);
+                    ssh_block = None;
+                }
+            } else if line.starts_with("-----BEGIN") {
+                ssh_block = Some(format!("{line}\n"));
+            } else if !line.is_empty() && !line.starts_with('#') {
+                result.push(SecretKey::X25519(x25519::Identity::from_str(&line)?));
             }
         }
 
+        if ssh_block.is_some() {
+            return Err("Incomplete SSH private key".into());
+        }
+
         Ok(result)
     }
 
+    /// Recipient of an SSH private key, without requiring its passphrase.
+    pub fn ssh_recipient<R: Read>(
+        reader: R,
+        filename: Option<String>,
+        name: &str,
+    ) -> Result<Recipient, Box<dyn Error>> {
+        let identity = ssh::Identity::from_buffer(BufReader::new(reader), filename)?;
+
+        Ok(Recipient {
+            name: name.to_string(),
+            key: ssh_public_key(&identity)?,
+        })
+    }
+
     pub fn to_age(&self) -> &dyn age::Identity {
-        &self.0
+        match self {
+            SecretKey::X25519(identity) => identity,
+            SecretKey::Ssh { identity, .. } => identity,
+        }
+    }
+}
+
+fn read_ssh_identity(pem: &str, filename: Option<String>) -> Result<SecretKey, Box<dyn Error>> {
+    let identity = ssh::Identity::from_buffer(pem.as_bytes(), filename.clone())?;
+    let public_key = ssh_public_key(&identity)?;
+
+    let identity = match identity {
+        ssh::Identity::Encrypted(key) => {
+            let passphrase = request_passphrase(&format!(
+                "Passphrase for {}",
+                filename.as_deref().unwrap_or("SSH key")
+            ))?;
+
+            ssh::Identity::from(key.decrypt(passphrase)?)
+        }
+        identity => identity,
+    };
+
+    Ok(SecretKey::Ssh {
+        identity,
+        public_key,
+    })
+}
+
+fn ssh_public_key(identity: &ssh::Identity) -> Result<String, Box<dyn Error>> {
+    if let ssh::Identity::Unsupported(_) = identity {
+        return Err("Unsupported SSH key (only ssh-ed25519 and ssh-rsa are supported)".into());
     }
+
+    ssh::Recipient::try_from(identity.clone())
+        .map(|recipient| recipient.to_string())
+        .map_err(|err| format!("Unsupported SSH key: {err:?}").into())
+}
+
+pub fn is_protected(content: &[u8]) -> bool {
+    content.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----")
+        || content.starts_with(b"age-encryption.org/")
+}
+
+/// Encrypt the content of a keys file with a passphrase.
+pub fn protect_keys(content: &[u8], passphrase: SecretString) -> Result<Vec<u8>, Box<dyn Error>> {
+    let encryptor = Encryptor::with_user_passphrase(passphrase);
+    let mut protected = Vec::new();
+    let mut output = encryptor.wrap_output(ArmoredWriter::wrap_output(
+        &mut protected,
+        Format::AsciiArmor,
+    )?)?;
+
+    output.write_all(content)?;
+    output.finish()?.finish()?;
+
+    Ok(protected)
+}
+
+/// Decrypt the content of a keys file protected by a passphrase.
+pub fn unprotect_keys(content: &[u8], passphrase: SecretString) -> Result<Vec<u8>, Box<dyn Error>> {
+    let decryptor = Decryptor::new(ArmoredReader::new(content))?;
+
+    if !decryptor.is_scrypt() {
+        return Err("Keys file is not protected by a passphrase".into());
+    }
+    let identity = scrypt::Identity::new(passphrase);
+
+    let mut plain = Vec::new();
+    decryptor
+        .decrypt(iter::once(&identity as &dyn age::Identity))?
+        .read_to_end(&mut plain)?;
+
+    Ok(plain)
+}
+
+/// Passphrase from `DOTIUM_PASSPHRASE`, the program in `DOTIUM_ASKPASS` or an interactive prompt.
+pub fn request_passphrase(prompt: &str) -> Result<SecretString, Box<dyn Error>> {
+    if let Some(passphrase) = lookup_passphrase(prompt, |name| env::var(name).ok())? {
+        return Ok(passphrase);
+    }
+
+    let passphrase = Password::new().with_prompt(prompt).interact()?;
+
+    Ok(SecretString::from(passphrase))
+}
+
+/// Passphrase from the `DOTIUM_PASSPHRASE` or `DOTIUM_ASKPASS` variable, if one of them is set.
+pub fn lookup_passphrase(
+    prompt: &str,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<Option<SecretString>, Box<dyn Error>> {
+    if let Some(passphrase) = var(PASSPHRASE_ENV) {
+        return Ok(Some(SecretString::from(passphrase)));
+    }
+    if let Some(askpass) = var(ASKPASS_ENV) {
+        let output = Command::new(&askpass)
+            .arg(prompt)
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .output()?;
+
+        if !output.status.success() {
+            return Err(format!("{askpass} failed with {}", output.status).into());
+        }
+        let passphrase = String::from_utf8(output.stdout)?;
+
+        return Ok(Some(SecretString::from(
+            passphrase.trim_end_matches(['\r', '\n']).to_string(),
+        )));
+    }
+
+    Ok(None)
+}
+
+/// Like `request_passphrase`, but the interactive prompt asks for confirmation.
+pub fn request_new_passphrase(prompt: &str) -> Result<SecretString, Box<dyn Error>> {
+    if env::var(PASSPHRASE_ENV).is_ok() || env::var(ASKPASS_ENV).is_ok() {
+        return request_passphrase(prompt);
+    }
+
+    let passphrase = Password::new()
+        .with_prompt(prompt)
+        .with_confirmation("Repeat passphrase", "Passphrases do not match")
+        .interact()?;
+
+    Ok(SecretString::from(passphrase))
 }
@@ -1,17 +1,22 @@
 use std::{
     error::Error,
     fs,
-    io::{self, Read, Write},
-    path::PathBuf,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
 };
 
 use age::{
     armor::{ArmoredReader, ArmoredWriter, Format},
-    Decryptor, Encryptor, Recipient,
+    Decryptor, Encryptor,
 };
+use base64::{
+    engine::general_purpose::{STANDARD, STANDARD_NO_PAD},
+    Engine,
+};
+use sha2::{Digest, Sha256};
 
 use crate::{
-    model::{FileDescriptor, SecretKey},
+    model::{FileDescriptor, Recipient, SecretKey},
     repository::{file_ref::RepositoryInfo, Environment},
 };
 
@@ -23,14 +28,7 @@ pub fn create_from_target<E: Environment>(
     let home = E::home_dir()?;
     let target = home.join(&file.target);
     let source = info.directory.join(dir_path).join(&file.source);
-    let encryptor = Encryptor::with_recipients(
-        info.recipients
-            .iter()
-            .map(|r| r.to_age())
-            .collect::<Result<Vec<Box<dyn Recipient>>, Box<dyn Error>>>()?
-            .iter()
-            .map(|r| r.as_ref()),
-    )?;
+    let encryptor = encryptor(&info.recipients_for(file)?)?;
 
     if let Some(parent) = source.parent() {
         fs::create_dir_all(parent)?;
@@ -58,8 +56,28 @@ pub fn get_content<E: Environment>(
     dir_path: &PathBuf,
     file: &FileDescriptor,
 ) -> Result<Vec<u8>, Box<dyn Error>> {
-    let source = info.directory.join(dir_path).join(&file.source);
-    let decryptor = Decryptor::new(ArmoredReader::new(fs::File::open(source)?))?;
+    decrypt_file(
+        &info.directory.join(dir_path).join(&file.source),
+        secret_keys,
+    )
+}
+
+pub fn set_content<E: Environment>(
+    info: &RepositoryInfo<E>,
+    dir_path: &PathBuf,
+    file: &FileDescriptor,
+    content: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    encrypt_file(
+        &info.directory.join(dir_path).join(&file.source),
+        &info.recipients_for(file)?,
+        content,
+    )
+}
+
+/// Decrypt an (armored) age file with any of the secret keys.
+pub fn decrypt_file(path: &Path, secret_keys: &[SecretKey]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let decryptor = Decryptor::new(ArmoredReader::new(fs::File::open(path)?))?;
 
     let mut content = vec![];
     decryptor
@@ -69,27 +87,19 @@ pub fn get_content<E: Environment>(
     Ok(content)
 }
 
-pub fn set_content<E: Environment>(
-    info: &RepositoryInfo<E>,
-    dir_path: &PathBuf,
-    file: &FileDescriptor,
+/// Encrypt content for the recipients into an (armored) age file.
+pub fn encrypt_file(
+    path: &Path,
+    recipients: &[&Recipient],
     content: &[u8],
 ) -> Result<(), Box<dyn Error>> {
-    let source = info.directory.join(dir_path).join(&file.source);
-    let encryptor = Encryptor::with_recipients(
-        info.recipients
-            .iter()
-            .map(|r| r.to_age())
-            .collect::<Result<Vec<Box<dyn Recipient>>, Box<dyn Error>>>()?
-            .iter()
-            .map(|r| r.as_ref()),
-    )?;
+    let encryptor = encryptor(recipients)?;
 
     let output_file = fs::OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(true)
-        .open(source)?;
+        .open(path)?;
 
     let mut output =
         encryptor.wrap_output(ArmoredWriter::wrap_output(output_file, Format::AsciiArmor)?)?;
@@ -100,3 +110,94 @@ pub fn set_content<E: Environment>(
 
     Ok(())
 }
+
+/// Recipient stanza in the header of an age file.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Stanza {
+    /// X25519 stanzas do not reveal the recipient, only their number can be compared
+    X25519,
+    /// SSH stanzas carry a tag of the public key
+    Ssh { key_type: String, tag: String },
+}
+
+impl Stanza {
+    fn for_recipient(recipient: &Recipient) -> Result<Stanza, Box<dyn Error>> {
+        if !recipient.is_ssh() {
+            return Ok(Stanza::X25519);
+        }
+        let mut parts = recipient.key.split_whitespace();
+        let (key_type, key) = parts
+            .next()
+            .zip(parts.next())
+            .ok_or_else(|| format!("Invalid SSH key of {}", recipient.name))?;
+        // Tag is the first 4 bytes of the SHA-256 of the key in SSH wire format
+        let digest = Sha256::digest(STANDARD.decode(key)?);
+
+        Ok(Stanza::Ssh {
+            key_type: key_type.to_string(),
+            tag: STANDARD_NO_PAD.encode(&digest[..4]),
+        })
+    }
+}
+
+/// Recipient stanzas in the header of an (armored) age file, grease stanzas are skipped.
+fn read_stanzas(path: &Path) -> Result<Vec<Stanza>, Box<dyn Error>> {
+    let mut lines = BufReader::new(ArmoredReader::new(fs::File::open(path)?)).split(b'\n');
+
+    match lines.next().transpose()? {
+        Some(line) if line.starts_with(b"age-encryption.org/") => {}
+        _ => return Err(format!("{} is not an age file", path.to_string_lossy()).into()),
+    }
+
+    let mut stanzas = Vec::new();
+    for line in lines {
+        let line = line?;
+
+        if line.starts_with(b"---") {
+            break;
+        }
+        if let Some(stanza) = line.strip_prefix(b"-> ") {
+            let stanza = String::from_utf8_lossy(stanza);
+            let mut args = stanza.split_whitespace();
+
+            match args.next() {
+                Some("X25519") => stanzas.push(Stanza::X25519),
+                Some(key_type @ ("ssh-ed25519" | "ssh-rsa")) => stanzas.push(Stanza::Ssh {
+                    key_type: key_type.to_string(),
+                    tag: args.next().unwrap_or_default().to_string(),
+                }),
+                _ => (),
+            }
+        }
+    }
+
+    Ok(stanzas)
+}
+
+/// Whether the header of an age file matches the recipients.
+///
+/// Without access to any secret key this can only be decided by the number of X25519 stanzas,
+/// i.e. an X25519 recipient replaced by another one is not noticed.
+pub fn is_encrypted_for(path: &Path, recipients: &[&Recipient]) -> Result<bool, Box<dyn Error>> {
+    let mut expected = recipients
+        .iter()
+        .map(|r| Stanza::for_recipient(r))
+        .collect::<Result<Vec<Stanza>, Box<dyn Error>>>()?;
+    let mut actual = read_stanzas(path)?;
+
+    expected.sort();
+    actual.sort();
+
+    Ok(expected == actual)
+}
+
+fn encryptor(recipients: &[&Recipient]) -> Result<Encryptor, Box<dyn Error>> {
+    let recipients = recipients
+        .iter()
+        .map(|r| r.to_age())
+        .collect::<Result<Vec<Box<dyn age::Recipient>>, Box<dyn Error>>>()?;
+
+    Ok(Encryptor::with_recipients(
+        recipients.iter().map(|r| r.as_ref()),
+    )?)
+}
@@ -1,4 +1,9 @@
-use std::{error::Error, fs, path::PathBuf};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    str,
+};
 
 use tera::{Context, Tera};
 
@@ -7,6 +12,9 @@ use crate::{
     repository::{file_ref::RepositoryInfo, Environment},
 };
 
+/// Directory in the repository with templates that can be included, imported or extended
+pub const TEMPLATES_DIR: &str = "_templates";
+
 pub fn create_from_target<E: Environment>(
     info: &RepositoryInfo<E>,
     dir_path: &PathBuf,
@@ -14,10 +22,7 @@ pub fn create_from_target<E: Environment>(
 ) -> Result<(), Box<dyn Error>> {
     let home = E::home_dir()?;
     let target = home.join(&file.target);
-    let source = info
-        .directory
-        .join(dir_path)
-        .join(format!("{}.j2", &file.source));
+    let source = info.directory.join(dir_path).join(&file.source);
 
     if let Some(parent) = source.parent() {
         fs::create_dir_all(parent)?;
@@ -46,20 +51,30 @@ pub fn get_rendered<E: Environment>(
     file: &FileDescriptor,
 ) -> Result<Vec<u8>, Box<dyn Error>> {
     let source = info.directory.join(dir_path).join(&file.source);
-    let mut tera = Tera::default();
-    let mut context = Context::new();
 
-    context.insert("recipient", &file_context.machine.recipient.name);
-    context.insert("machine", &file_context.machine.variables);
+    render(info, dir_path, file, &fs::read(source)?, file_context)
+}
+
+/// Render the content of a template of a file along with the shared templates of the repository.
+pub fn render<E: Environment>(
+    info: &RepositoryInfo<E>,
+    dir_path: &Path,
+    file: &FileDescriptor,
+    content: &[u8],
+    file_context: &FileContext,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut tera = info.templates()?.clone();
+    let context = template_context(file_context);
+    let name = dir_path.join(&file.source).to_string_lossy().to_string();
 
-    if let Err(err) = tera.add_template_file(source, Some(&file.source)) {
+    if let Err(err) = tera.add_raw_template(&name, str::from_utf8(content)?) {
         return match err.source() {
             Some(source) => Err(format!("{err} {source}").into()),
             _ => Err(err.into()),
         };
     }
 
-    match tera.render(&file.source, &context) {
+    match tera.render(&name, &context) {
         Ok(content) => Ok(content.into_bytes()),
         Err(err) => match err.source() {
             Some(source) => Err(format!("{source}").into()),
@@ -68,6 +83,72 @@ pub fn get_rendered<E: Environment>(
     }
 }
 
+/// Load all templates of the `_templates` directory, named by their path relative to it.
+pub fn load_templates(directory: &Path) -> Result<Tera, Box<dyn Error>> {
+    let templates_dir = directory.join(TEMPLATES_DIR);
+    let mut tera = Tera::default();
+
+    if !templates_dir.is_dir() {
+        return Ok(tera);
+    }
+
+    let mut files = Vec::new();
+    collect_templates(&templates_dir, &templates_dir, &mut files)?;
+
+    if let Err(err) = tera.add_template_files(files) {
+        return match err.source() {
+            Some(source) => Err(format!("{err} {source}").into()),
+            _ => Err(err.into()),
+        };
+    }
+
+    Ok(tera)
+}
+
+fn collect_templates(
+    templates_dir: &Path,
+    directory: &Path,
+    files: &mut Vec<(PathBuf, Option<String>)>,
+) -> Result<(), Box<dyn Error>> {
+    for entry in directory.read_dir()? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_templates(templates_dir, &path, files)?;
+        } else {
+            let name = path
+                .strip_prefix(templates_dir)?
+                .components()
+                .map(|part| part.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            files.push((path, Some(name)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Variables available to templates (and conditions of files).
+///
+/// `machine` and `repository` are the single layers, `variables` is the merged result.
+pub fn template_context(file_context: &FileContext) -> Context {
+    let mut context = Context::new();
+
+    context.insert("recipient", &file_context.machine.recipient.name);
+    context.insert("system", file_context.system);
+    context.insert("file", &file_context.file);
+    context.insert("machine", &file_context.machine.variables);
+    context.insert("repository", &file_context.repository);
+    context.insert("variables", &file_context.variables());
+    if let Some(secrets) = file_context.secrets {
+        context.insert("secrets", secrets);
+    }
+
+    context
+}
+
 pub fn set_content<E: Environment>(
     info: &RepositoryInfo<E>,
     dir_path: &PathBuf,
@@ -8,14 +8,19 @@ mod as_is;
 mod crypted;
 mod j2_template;
 
+pub use crypted::{decrypt_file, encrypt_file, is_encrypted_for};
+pub use j2_template::{load_templates, render as render_template, template_context, TEMPLATES_DIR};
+
 pub fn create_from_target<E: Environment>(
     info: &RepositoryInfo<E>,
     dir_path: &PathBuf,
     file: &FileDescriptor,
 ) -> Result<(), Box<dyn Error>> {
     match file.action {
-        FileAction::AsIs => as_is::create_from_target(info, dir_path, file),
-        FileAction::Crypted => crypted::create_from_target(info, dir_path, file),
+        FileAction::AsIs | FileAction::Symlink => as_is::create_from_target(info, dir_path, file),
+        FileAction::Crypted | FileAction::CryptedJ2 => {
+            crypted::create_from_target(info, dir_path, file)
+        }
         FileAction::J2 => j2_template::create_from_target(info, dir_path, file),
     }
 }
@@ -27,8 +32,10 @@ pub fn get_content<E: Environment>(
     file: &FileDescriptor,
 ) -> Result<Vec<u8>, Box<dyn Error>> {
     match file.action {
-        FileAction::AsIs => as_is::get_content(info, dir_path, file),
-        FileAction::Crypted => crypted::get_content(info, secret_keys, dir_path, file),
+        FileAction::AsIs | FileAction::Symlink => as_is::get_content(info, dir_path, file),
+        FileAction::Crypted | FileAction::CryptedJ2 => {
+            crypted::get_content(info, secret_keys, dir_path, file)
+        }
         FileAction::J2 => j2_template::get_content(info, dir_path, file),
     }
 }
@@ -41,9 +48,16 @@ pub fn get_rendered<E: Environment>(
     file: &FileDescriptor,
 ) -> Result<Vec<u8>, Box<dyn Error>> {
     match file.action {
-        FileAction::AsIs => as_is::get_content(info, dir_path, file),
+        FileAction::AsIs | FileAction::Symlink => as_is::get_content(info, dir_path, file),
         FileAction::Crypted => crypted::get_content(info, secret_keys, dir_path, file),
         FileAction::J2 => j2_template::get_rendered(info, file_context, dir_path, file),
+        FileAction::CryptedJ2 => j2_template::render(
+            info,
+            dir_path,
+            file,
+            &crypted::get_content(info, secret_keys, dir_path, file)?,
+            file_context,
+        ),
     }
 }
 
@@ -54,8 +68,10 @@ pub fn set_content<E: Environment>(
     content: &[u8],
 ) -> Result<(), Box<dyn Error>> {
     match file.action {
-        FileAction::AsIs => as_is::set_content(info, dir_path, file, content),
-        FileAction::Crypted => crypted::set_content(info, dir_path, file, content),
+        FileAction::AsIs | FileAction::Symlink => as_is::set_content(info, dir_path, file, content),
+        FileAction::Crypted | FileAction::CryptedJ2 => {
+            crypted::set_content(info, dir_path, file, content)
+        }
         FileAction::J2 => j2_template::set_content(info, dir_path, file, content),
     }
 }
@@ -0,0 +1,115 @@
+use std::{
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+const MAX_SYMLINKS: usize = 40;
+
+/// Follow symlinks at a target to the file that should actually be written, so that the links are kept.
+pub fn resolve_symlinks(target: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let mut resolved = target.to_path_buf();
+
+    for _ in 0..MAX_SYMLINKS {
+        match fs::symlink_metadata(&resolved) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let link = fs::read_link(&resolved)?;
+
+                resolved = match resolved.parent() {
+                    Some(parent) => parent.join(link),
+                    None => link,
+                };
+            }
+            _ => return Ok(resolved),
+        }
+    }
+
+    Err(format!("Too many levels of symlinks: {}", target.to_string_lossy()).into())
+}
+
+/// Write to a temporary file next to the target (with final permissions already set)
+/// and rename it into place, so that the target is never left truncated or readable
+/// by others.
+pub fn write_file(
+    target: &Path,
+    content: &[u8],
+    permissions: Option<fs::Permissions>,
+) -> Result<(), Box<dyn Error>> {
+    let tmp_file = tmp_file(target)?;
+
+    // Leftover of a previous run that crashed
+    fs::remove_file(&tmp_file).ok();
+
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        // Never readable by others, not even before the permissions are set below
+        #[cfg(unix)]
+        if let Some(permissions) = &permissions {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+            options.mode(permissions.mode());
+        }
+        let mut file = options.open(&tmp_file)?;
+
+        if let Some(permissions) = permissions {
+            file.set_permissions(permissions)?;
+        }
+        file.write_all(content)?;
+        file.sync_all()?;
+
+        fs::rename(&tmp_file, target)?;
+
+        Ok(())
+    })();
+
+    if result.is_err() {
+        fs::remove_file(&tmp_file).ok();
+    }
+    result?;
+
+    #[cfg(unix)]
+    if let Some(parent) = target.parent() {
+        fs::File::open(parent)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Replace a target with a symlink to `source` without a moment where the target is missing.
+pub fn write_symlink(target: &Path, source: &Path) -> Result<(), Box<dyn Error>> {
+    let tmp_file = tmp_file(target)?;
+
+    fs::remove_file(&tmp_file).ok();
+    symlink(source, &tmp_file)?;
+
+    if let Err(error) = fs::rename(&tmp_file, target) {
+        fs::remove_file(&tmp_file).ok();
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+fn tmp_file(target: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let file_name = target
+        .file_name()
+        .ok_or_else(|| format!("Invalid target: {}", target.to_string_lossy()))?;
+
+    Ok(target.with_file_name(format!(
+        ".{}.dotium-{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    )))
+}
+
+#[cfg(unix)]
+fn symlink(source: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(source, link)
+}
+
+#[cfg(windows)]
+fn symlink(source: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(source, link)
+}
@@ -0,0 +1,210 @@
+use std::{
+    error::Error,
+    fs,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use crate::model::{BackupDescriptor, BackupEntry};
+
+use super::{atomic, Environment};
+
+/// Number of backups kept by `Backup::prune`, older ones are removed since they may hold secrets.
+pub const BACKUPS_KEPT: usize = 20;
+
+/// Previous state of all targets touched by a single run (e.g. an `apply`).
+///
+/// The backup directory is only created once the first target is saved.
+#[derive(Debug)]
+pub struct Backup<E> {
+    pub id: String,
+    pub directory: PathBuf,
+    pub descriptor: BackupDescriptor,
+    phantom: PhantomData<E>,
+}
+
+impl<E> Backup<E>
+where
+    E: Environment,
+{
+    pub fn new<P: AsRef<Path>>(backups_dir: P) -> Self {
+        let now = chrono::Local::now();
+        let id = now.format("%Y%m%d-%H%M%S-%3f").to_string();
+
+        Backup {
+            directory: backups_dir.as_ref().join(&id),
+            id,
+            descriptor: BackupDescriptor {
+                created: now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                entries: vec![],
+            },
+            phantom: PhantomData,
+        }
+    }
+
+    /// All backups in `backups_dir`, oldest first.
+    pub fn list<P: AsRef<Path>>(backups_dir: P) -> Result<Vec<Self>, Box<dyn Error>> {
+        let mut backups = Vec::new();
+
+        if !backups_dir.as_ref().is_dir() {
+            return Ok(backups);
+        }
+
+        for entry in backups_dir.as_ref().read_dir()? {
+            let directory = entry?.path();
+            let backup_file = directory.join("backup.json");
+
+            if !backup_file.is_file() {
+                continue;
+            }
+            let descriptor = serde_json::from_reader(fs::File::open(&backup_file)?)?;
+
+            backups.push(Backup {
+                id: directory
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                directory,
+                descriptor,
+                phantom: PhantomData,
+            });
+        }
+        backups.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(backups)
+    }
+
+    /// Remove all but the `keep` newest backups in `backups_dir`, returning the removed ids.
+    pub fn prune<P: AsRef<Path>>(
+        backups_dir: P,
+        keep: usize,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let backups = Self::list(backups_dir)?;
+        let obsolete = backups.len().saturating_sub(keep);
+        let mut removed = Vec::new();
+
+        for backup in backups.into_iter().take(obsolete) {
+            fs::remove_dir_all(&backup.directory)?;
+            removed.push(backup.id);
+        }
+
+        Ok(removed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.descriptor.entries.is_empty()
+    }
+
+    pub fn entry(&self, target: &Path) -> Option<&BackupEntry> {
+        self.descriptor
+            .entries
+            .iter()
+            .find(|entry| entry.target == target)
+    }
+
+    /// Save the current state of a target before it gets modified.
+    ///
+    /// Only the first state of a target is kept, so that a run can be rolled back as a whole.
+    /// Symlinks are saved as links, not by the content they point to.
+    pub fn save(&mut self, target: &Path) -> Result<(), Box<dyn Error>> {
+        if self.entry(target).is_some() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.directory)?;
+        if let Some(permissions) = E::permission_from_string("0700") {
+            fs::set_permissions(&self.directory, permissions)?;
+        }
+
+        let metadata = fs::symlink_metadata(target).ok();
+
+        let entry = if metadata
+            .as_ref()
+            .is_some_and(|m| m.file_type().is_symlink())
+        {
+            BackupEntry {
+                target: target.to_path_buf(),
+                content: None,
+                permission: None,
+                link: Some(fs::read_link(target)?),
+            }
+        } else if let Some(metadata) = metadata.filter(|m| m.is_file()) {
+            let content = format!("{}", self.descriptor.entries.len());
+            let backup_file = self.directory.join(&content);
+
+            fs::copy(target, &backup_file)?;
+            // Backups may contain secrets, so they are only readable by the owner
+            if let Some(permissions) = E::permission_from_string("0600") {
+                fs::set_permissions(&backup_file, permissions)?;
+            }
+
+            BackupEntry {
+                target: target.to_path_buf(),
+                content: Some(content),
+                permission: Some(E::permission_to_string(metadata.permissions())),
+                link: None,
+            }
+        } else {
+            BackupEntry {
+                target: target.to_path_buf(),
+                content: None,
+                permission: None,
+                link: None,
+            }
+        };
+
+        self.descriptor.entries.push(entry);
+        self.store()
+    }
+
+    /// Restore a target to the state stored in this backup.
+    ///
+    /// The state that is replaced is saved to `current` first. The target itself is
+    /// replaced, i.e. a symlink at the target is not followed. A target that did not
+    /// exist at the time of the backup is removed, unless it is something else than a
+    /// regular file by now (e.g. a symlink created afterwards).
+    pub fn restore(
+        &self,
+        entry: &BackupEntry,
+        current: &mut Backup<E>,
+    ) -> Result<(), Box<dyn Error>> {
+        current.save(&entry.target)?;
+
+        if entry.content.is_some() || entry.link.is_some() {
+            if let Some(parent) = entry.target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        match (&entry.content, &entry.link) {
+            (Some(content), _) => atomic::write_file(
+                &entry.target,
+                &fs::read(self.directory.join(content))?,
+                entry
+                    .permission
+                    .as_ref()
+                    .and_then(|permission| E::permission_from_string(permission)),
+            )?,
+            (None, Some(link)) => atomic::write_symlink(&entry.target, link)?,
+            (None, None) => {
+                if fs::symlink_metadata(&entry.target).is_ok_and(|m| m.is_file()) {
+                    fs::remove_file(&entry.target)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn store(&self) -> Result<(), Box<dyn Error>> {
+        let backup_file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self.directory.join("backup.json"))?;
+
+        serde_json::to_writer_pretty(backup_file, &self.descriptor)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    str,
+};
+
+use serde::Serialize;
+use tera::Tera;
+
+use crate::model::{SecretKey, FORMAT_VERSION};
+
+use super::{actions, condition, secrets::SECRETS_FILE, Environment, Repository};
+
+/// Problem of a repository found by `Repository::check`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "issue", rename_all = "kebab-case")]
+pub enum Issue {
+    /// Source of a tracked file is missing
+    MissingSource,
+    /// File in the repository not referenced by any descriptor
+    OrphanFile,
+    /// Directory listed in `dotium.json` without a `dotium_dir.json`
+    MissingDescriptor,
+    /// Repository has to be upgraded with `dotium migrate`
+    OutdatedFormat {
+        format_version: u32,
+    },
+    DuplicateTarget {
+        target: PathBuf,
+        sources: Vec<String>,
+    },
+    InvalidPermission {
+        permission: String,
+    },
+    InvalidCondition {
+        message: String,
+    },
+    InvalidRecipients {
+        message: String,
+    },
+    InvalidTemplate {
+        message: String,
+    },
+    CannotDecrypt {
+        message: String,
+    },
+    /// Encrypted file not (or no longer) encrypted for its recipients, see `dotium reencrypt`
+    StaleEncryption,
+    Unreadable {
+        message: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckEntry {
+    /// Path relative to the repository
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub issue: Issue,
+}
+
+/// Without secret keys, encrypted content (files and secrets) is not checked.
+pub fn check<E: Environment>(
+    repository: &Repository<E>,
+    secret_keys: Option<&[SecretKey]>,
+) -> Result<Vec<CheckEntry>, Box<dyn Error>> {
+    let directory = &repository.info.directory;
+    let mut entries = Vec::new();
+    let mut referenced = HashSet::new();
+    let mut targets = BTreeMap::<PathBuf, Vec<String>>::new();
+
+    if repository.root.format_version < FORMAT_VERSION {
+        entries.push(CheckEntry {
+            path: PathBuf::from("dotium.json"),
+            issue: Issue::OutdatedFormat {
+                format_version: repository.root.format_version,
+            },
+        });
+    }
+    for dir_path in &repository.root.directories {
+        if repository.dirs.contains_key(dir_path) {
+            referenced.insert(dir_path.join("dotium_dir.json"));
+        } else {
+            entries.push(CheckEntry {
+                path: dir_path.join("dotium_dir.json"),
+                issue: Issue::MissingDescriptor,
+            });
+        }
+    }
+
+    let templates = match repository.info.templates() {
+        Ok(templates) => templates.clone(),
+        Err(error) => {
+            entries.push(CheckEntry {
+                path: PathBuf::from(actions::TEMPLATES_DIR),
+                issue: Issue::InvalidTemplate {
+                    message: error.to_string(),
+                },
+            });
+            Tera::default()
+        }
+    };
+
+    let mut files = repository.files().collect::<Vec<_>>();
+    files.sort();
+
+    for file_ref in files {
+        let path = file_ref.dir_path.join(&file_ref.file.source);
+        let mut report = |issue| {
+            entries.push(CheckEntry {
+                path: path.clone(),
+                issue,
+            })
+        };
+
+        referenced.insert(path.clone());
+        targets
+            .entry(file_ref.file.target.clone())
+            .or_default()
+            .push(path.to_string_lossy().to_string());
+
+        if let Some(permission) = &file_ref.file.permission {
+            if !is_valid_permission(permission) {
+                report(Issue::InvalidPermission {
+                    permission: permission.clone(),
+                });
+            }
+        }
+        if let Some(condition) = &file_ref.file.condition {
+            if let Err(error) = condition::validate(condition) {
+                report(Issue::InvalidCondition {
+                    message: error.to_string(),
+                });
+            }
+        }
+        if let Err(error) = repository.info.recipients_for(&file_ref.file) {
+            report(Issue::InvalidRecipients {
+                message: error.to_string(),
+            });
+        }
+
+        if !file_ref.absolute_source().is_file() {
+            report(Issue::MissingSource);
+            continue;
+        }
+        match file_ref.is_stale() {
+            Ok(true) => report(Issue::StaleEncryption),
+            Ok(false) => (),
+            // Invalid recipients are already reported
+            Err(_) if file_ref.file.recipients.is_some() => (),
+            Err(error) => report(Issue::CannotDecrypt {
+                message: error.to_string(),
+            }),
+        }
+        let secret_keys = match secret_keys {
+            Some(secret_keys) => secret_keys,
+            None if file_ref.file.action.is_crypted() => continue,
+            None => &[],
+        };
+        // Files restricted to other recipients cannot be decrypted here by design
+        if file_ref.is_excluded(secret_keys) {
+            continue;
+        }
+        let content = match file_ref.get_content(secret_keys) {
+            Ok(content) => content,
+            Err(error) => {
+                report(if file_ref.file.action.is_crypted() {
+                    Issue::CannotDecrypt {
+                        message: error.to_string(),
+                    }
+                } else {
+                    Issue::Unreadable {
+                        message: error.to_string(),
+                    }
+                });
+                continue;
+            }
+        };
+        if file_ref.file.action.is_template() {
+            if let Err(error) = parse_template(&templates, &path, &content) {
+                report(Issue::InvalidTemplate {
+                    message: error.to_string(),
+                });
+            }
+        }
+    }
+
+    for (target, sources) in targets {
+        if sources.len() > 1 {
+            entries.push(CheckEntry {
+                path: PathBuf::from(&sources[0]),
+                issue: Issue::DuplicateTarget { target, sources },
+            });
+        }
+    }
+
+    if directory.join(SECRETS_FILE).is_file() {
+        referenced.insert(PathBuf::from(SECRETS_FILE));
+
+        if let Some(Err(error)) = secret_keys.map(|keys| repository.info.secrets(keys)) {
+            entries.push(CheckEntry {
+                path: PathBuf::from(SECRETS_FILE),
+                issue: Issue::CannotDecrypt {
+                    message: error.to_string(),
+                },
+            });
+        }
+        match repository.secrets_stale() {
+            Ok(true) => entries.push(CheckEntry {
+                path: PathBuf::from(SECRETS_FILE),
+                issue: Issue::StaleEncryption,
+            }),
+            Ok(false) => (),
+            Err(error) => entries.push(CheckEntry {
+                path: PathBuf::from(SECRETS_FILE),
+                issue: Issue::Unreadable {
+                    message: error.to_string(),
+                },
+            }),
+        }
+    }
+
+    let mut orphans = Vec::new();
+    collect_orphans(directory, directory, &referenced, &mut orphans)?;
+    orphans.sort();
+    entries.extend(orphans.into_iter().map(|path| CheckEntry {
+        path,
+        issue: Issue::OrphanFile,
+    }));
+
+    Ok(entries)
+}
+
+/// Permissions are stored as octal mode, e.g. `0644`.
+fn is_valid_permission(permission: &str) -> bool {
+    (3..=4).contains(&permission.len())
+        && u32::from_str_radix(permission, 8).is_ok_and(|mode| mode <= 0o7777)
+}
+
+fn parse_template(templates: &Tera, path: &Path, content: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut tera = templates.clone();
+
+    match tera.add_raw_template(&path.to_string_lossy(), str::from_utf8(content)?) {
+        Ok(()) => Ok(()),
+        Err(err) => match err.source() {
+            Some(source) => Err(format!("{err} {source}").into()),
+            None => Err(err.into()),
+        },
+    }
+}
+
+/// Files of the repository that are neither descriptors nor sources of tracked files.
+///
+/// Hidden files (like `.git`), the root descriptor and shared templates are skipped.
+fn collect_orphans(
+    root: &Path,
+    directory: &Path,
+    referenced: &HashSet<PathBuf>,
+    orphans: &mut Vec<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let relative = path.strip_prefix(root)?.to_path_buf();
+        let name = relative
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        if name.starts_with('.')
+            || relative == Path::new("dotium.json")
+            || relative == Path::new(actions::TEMPLATES_DIR)
+        {
+            continue;
+        }
+        if path.is_dir() {
+            collect_orphans(root, &path, referenced, orphans)?;
+        } else if !referenced.contains(&relative) {
+            orphans.push(relative);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use std::error::Error;
+
+use tera::Tera;
+
+use crate::model::FileContext;
+
+use super::actions::template_context;
+
+/// Evaluate the condition of a file, a Tera expression like `os == "linux" and machine.work`.
+///
+/// Available are `os`, `arch` and everything available to templates.
+/// Use `variables.work is defined` for variables that are not set on every machine.
+pub fn evaluate(condition: &str, file_context: &FileContext) -> Result<bool, Box<dyn Error>> {
+    let mut context = template_context(file_context);
+
+    context.insert("os", &file_context.system.os);
+    context.insert("arch", &file_context.system.arch);
+
+    match Tera::one_off(&template(condition), &context, false) {
+        Ok(result) => Ok(result == "true"),
+        Err(err) => Err(tera_error("Cannot evaluate condition", condition, err)),
+    }
+}
+
+/// Check the syntax of a condition without evaluating it.
+pub fn validate(condition: &str) -> Result<(), Box<dyn Error>> {
+    Tera::default()
+        .add_raw_template("condition", &template(condition))
+        .map_err(|err| tera_error("Invalid condition", condition, err))
+}
+
+fn template(condition: &str) -> String {
+    format!("{{% if {condition} %}}true{{% endif %}}")
+}
+
+fn tera_error(message: &str, condition: &str, err: tera::Error) -> Box<dyn Error> {
+    match err.source() {
+        Some(source) => format!("{message} '{condition}': {source}").into(),
+        None => format!("{message} '{condition}': {err}").into(),
+    }
+}
@@ -1,10 +1,16 @@
-use std::{error::Error, fs, path::PathBuf};
+use std::{env, error::Error, fs, path::PathBuf};
+
+use gethostname::gethostname;
+
+use crate::model::{SystemContext, XdgDirs};
 
 pub trait Environment {
     fn home_dir() -> Result<PathBuf, Box<dyn Error>>;
 
     fn config_dir() -> Result<PathBuf, Box<dyn Error>>;
 
+    fn system() -> Result<SystemContext, Box<dyn Error>>;
+
     fn permission_to_string(permissions: fs::Permissions) -> String;
 
     fn permission_from_string(text: &str) -> Option<fs::Permissions>;
@@ -24,6 +30,24 @@ impl Environment for DefaultEnvironment {
             .ok_or("Unable to get config dir")?)
     }
 
+    fn system() -> Result<SystemContext, Box<dyn Error>> {
+        Ok(SystemContext {
+            hostname: gethostname().to_string_lossy().to_string(),
+            os: env::consts::OS.to_string(),
+            distribution: distribution(),
+            arch: env::consts::ARCH.to_string(),
+            username: username(),
+            uid: uid(),
+            home: Self::home_dir()?,
+            xdg: XdgDirs {
+                config_home: dirs::config_dir(),
+                data_home: dirs::data_dir(),
+                cache_home: dirs::cache_dir(),
+                state_home: dirs::state_dir(),
+            },
+        })
+    }
+
     #[cfg(unix)]
     fn permission_to_string(permissions: fs::Permissions) -> String {
         use std::os::unix::fs::PermissionsExt;
@@ -54,3 +78,32 @@ impl Environment for DefaultEnvironment {
         None
     }
 }
+
+fn distribution() -> Option<String> {
+    let os_release = fs::read_to_string("/etc/os-release").ok()?;
+
+    os_release.lines().find_map(|line| {
+        line.strip_prefix("ID=")
+            .map(|id| id.trim_matches('"').to_string())
+    })
+}
+
+#[cfg(unix)]
+fn username() -> Option<String> {
+    env::var("USER").or_else(|_| env::var("LOGNAME")).ok()
+}
+
+#[cfg(not(unix))]
+fn username() -> Option<String> {
+    env::var("USERNAME").ok()
+}
+
+#[cfg(unix)]
+fn uid() -> Option<u32> {
+    Some(rustix::process::getuid().as_raw())
+}
+
+#[cfg(not(unix))]
+fn uid() -> Option<u32> {
+    None
+}
@@ -1,4 +1,6 @@
 use std::{
+    cell::{OnceCell, RefCell},
+    collections::{BTreeMap, BTreeSet, HashSet},
     error::Error,
     fmt, fs,
     marker::PhantomData,
@@ -6,17 +8,116 @@ use std::{
     rc::Rc,
 };
 
-use crate::model::{FileAction, FileContext, FileDescriptor, MachineContext, Recipient, SecretKey};
+use tera::Tera;
 
-use super::{actions, outcome::OutcomeError, Environment, Outcome};
+use crate::model::{
+    FileAction, FileContext, FileDescriptor, FilePaths, MachineContext, Recipient,
+    RepositoryVariables, RootDescriptor, SecretKey, SystemContext,
+};
+
+use super::{
+    actions, atomic, condition, outcome::OutcomeError, secrets, secrets::Secrets, Environment,
+    Outcome,
+};
 
 #[derive(Debug)]
 pub struct RepositoryInfo<E> {
     pub directory: PathBuf,
     pub recipients: Vec<Recipient>,
+    pub groups: BTreeMap<String, Vec<String>>,
+    pub variables: RepositoryVariables,
+    /// Secrets are decrypted at most once, when the first template needs them
+    secrets: OnceCell<Result<Secrets, String>>,
+    /// Shared templates are loaded at most once, when the first template is rendered
+    templates: OnceCell<Result<Tera, String>>,
+    system: OnceCell<Result<SystemContext, String>>,
+    /// Files of the repository written or removed so far (shared with refreshed infos)
+    pub changes: Rc<RefCell<BTreeSet<PathBuf>>>,
     pub phantom: PhantomData<E>,
 }
 
+impl<E> RepositoryInfo<E> {
+    pub fn new(directory: PathBuf, root: &RootDescriptor) -> Self {
+        RepositoryInfo {
+            directory,
+            recipients: root.recipients.clone(),
+            groups: root.groups.clone(),
+            variables: root.variables.clone(),
+            secrets: OnceCell::new(),
+            templates: OnceCell::new(),
+            system: OnceCell::new(),
+            changes: Rc::new(RefCell::new(BTreeSet::new())),
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn record_change<P: Into<PathBuf>>(&self, path: P) {
+        self.changes.borrow_mut().insert(path.into());
+    }
+
+    pub fn secrets(&self, secret_keys: &[SecretKey]) -> Result<&Secrets, Box<dyn Error>> {
+        self.secrets
+            .get_or_init(|| {
+                secrets::read(&self.directory, secret_keys).map_err(|error| error.to_string())
+            })
+            .as_ref()
+            .map_err(|error| format!("Cannot decrypt secrets: {error}").into())
+    }
+
+    pub fn system(&self) -> Result<&SystemContext, Box<dyn Error>>
+    where
+        E: Environment,
+    {
+        self.system
+            .get_or_init(|| E::system().map_err(|error| error.to_string()))
+            .as_ref()
+            .map_err(|error| error.clone().into())
+    }
+
+    pub fn templates(&self) -> Result<&Tera, Box<dyn Error>> {
+        self.templates
+            .get_or_init(|| {
+                actions::load_templates(&self.directory).map_err(|error| error.to_string())
+            })
+            .as_ref()
+            .map_err(|error| format!("Cannot load shared templates: {error}").into())
+    }
+
+    /// Recipients an encrypted file has to be encrypted for.
+    pub fn recipients_for(&self, file: &FileDescriptor) -> Result<Vec<&Recipient>, Box<dyn Error>> {
+        let scope = match &file.recipients {
+            Some(scope) => scope,
+            None => return Ok(self.recipients.iter().collect()),
+        };
+
+        let mut names = HashSet::new();
+        for name in scope {
+            let group = self.groups.get(name);
+
+            if group.is_none() && !self.recipients.iter().any(|r| &r.name == name) {
+                return Err(format!(
+                    "Unknown recipient or group {name} for {}",
+                    file.target.to_string_lossy()
+                )
+                .into());
+            }
+            names.insert(name);
+            names.extend(group.into_iter().flatten());
+        }
+
+        let recipients = self
+            .recipients
+            .iter()
+            .filter(|r| names.contains(&r.name))
+            .collect::<Vec<&Recipient>>();
+        if recipients.is_empty() {
+            return Err(format!("No recipients left for {}", file.target.to_string_lossy()).into());
+        }
+
+        Ok(recipients)
+    }
+}
+
 #[derive(Debug)]
 pub struct FileRef<E> {
     pub repository: Rc<RepositoryInfo<E>>,
@@ -34,7 +135,10 @@ where
         action: FileAction,
     ) -> Result<Self, Box<dyn Error>> {
         let target = relative_target_file::<_, E>(target_file)?;
-        let (dir_path, source) = source_file_from_target(&target);
+        let (dir_path, mut source) = source_file_from_target(&target);
+        if action == FileAction::J2 {
+            source.push_str(".j2");
+        }
 
         let permissions = fs::metadata(E::home_dir()?.join(&target))?.permissions();
 
@@ -46,6 +150,8 @@ where
                 target,
                 action,
                 permission: Some(E::permission_to_string(permissions)),
+                recipients: None,
+                condition: None,
             },
         })
     }
@@ -63,6 +169,86 @@ where
             .join(&self.file.source)
     }
 
+    /// Absolute path the target of a `Symlink` file has to point to.
+    pub fn link_source(&self) -> Result<PathBuf, Box<dyn Error>> {
+        Ok(fs::canonicalize(self.absolute_source())?)
+    }
+
+    /// Replace the target by a symlink to the source in the repository.
+    pub fn link_target(&self) -> Result<(), Box<dyn Error>> {
+        atomic::write_symlink(&self.absolute_target()?, &self.link_source()?)
+    }
+
+    /// Replace a target that is a symlink to the source by a regular copy of the content.
+    pub fn unlink_target(&self) -> Result<(), Box<dyn Error>> {
+        let target = self.absolute_target()?;
+
+        if fs::read_link(&target).ok() == Some(self.link_source()?) {
+            let content = fs::read(&target)?;
+            let permissions = fs::metadata(&target)?.permissions();
+
+            atomic::write_file(&target, &content, Some(permissions))?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether an encrypted file is not encrypted for its current recipients (any more).
+    pub fn is_stale(&self) -> Result<bool, Box<dyn Error>> {
+        if !self.file.action.is_crypted() {
+            return Ok(false);
+        }
+
+        Ok(!actions::is_encrypted_for(
+            &self.absolute_source(),
+            &self.repository.recipients_for(&self.file)?,
+        )?)
+    }
+
+    /// Whether the file is restricted to recipients none of the secret keys belongs to.
+    ///
+    /// Such files are deliberately not readable on this machine, so they are skipped quietly.
+    pub fn is_excluded(&self, secret_keys: &[SecretKey]) -> bool {
+        if self.file.recipients.is_none() || !self.file.action.is_crypted() {
+            return false;
+        }
+
+        match self.repository.recipients_for(&self.file) {
+            Ok(recipients) => !recipients
+                .iter()
+                .any(|r| secret_keys.iter().any(|s| s.public_key() == r.key)),
+            Err(_) => false,
+        }
+    }
+
+    /// Whether the condition of the file (if any) matches the machine.
+    pub fn is_applicable(&self, machine: &MachineContext) -> Result<bool, Box<dyn Error>> {
+        match &self.file.condition {
+            Some(expression) => condition::evaluate(expression, &self.file_context(machine)?),
+            None => Ok(true),
+        }
+    }
+
+    fn file_context<'a>(
+        &'a self,
+        machine: &'a MachineContext,
+    ) -> Result<FileContext<'a>, Box<dyn Error>> {
+        Ok(FileContext {
+            machine,
+            system: self.repository.system()?,
+            file: FilePaths {
+                target: self.absolute_target()?,
+                source: self.absolute_source(),
+                repository: self.repository.directory.clone(),
+            },
+            repository: self
+                .repository
+                .variables
+                .for_recipient(&machine.recipient.name),
+            secrets: None,
+        })
+    }
+
     pub fn get_content(&self, secret_keys: &[SecretKey]) -> Result<Vec<u8>, Box<dyn Error>> {
         actions::get_content(&self.repository, secret_keys, &self.dir_path, &self.file)
     }
@@ -72,18 +258,56 @@ where
         machine: &MachineContext,
         secret_keys: &[SecretKey],
     ) -> Result<Vec<u8>, Box<dyn Error>> {
-        let file_context = FileContext { machine };
-        actions::get_rendered(
+        let mut file_context = self.file_context(machine)?;
+        if !self.file.action.is_template() {
+            return actions::get_rendered(
+                &self.repository,
+                &file_context,
+                secret_keys,
+                &self.dir_path,
+                &self.file,
+            );
+        }
+        // Templates without secrets have to work on machines that cannot decrypt them
+        let secrets = self.repository.secrets(secret_keys);
+        file_context.secrets = secrets.as_ref().ok().copied();
+
+        let rendered = actions::get_rendered(
             &self.repository,
             &file_context,
             secret_keys,
             &self.dir_path,
             &self.file,
-        )
+        );
+        with_secrets_error(rendered, secrets)
+    }
+
+    /// Render a (modified) template content as if it was the content of this file.
+    pub fn render(
+        &self,
+        content: &[u8],
+        machine: &MachineContext,
+        secret_keys: &[SecretKey],
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut file_context = self.file_context(machine)?;
+        let secrets = self.repository.secrets(secret_keys);
+        file_context.secrets = secrets.as_ref().ok().copied();
+
+        let rendered = actions::render_template(
+            &self.repository,
+            &self.dir_path,
+            &self.file,
+            content,
+            &file_context,
+        );
+        with_secrets_error(rendered, secrets)
     }
 
     pub fn set_content(&self, content: &[u8]) -> Result<(), Box<dyn Error>> {
-        actions::set_content(&self.repository, &self.dir_path, &self.file, content)
+        actions::set_content(&self.repository, &self.dir_path, &self.file, content)?;
+        self.repository.record_change(self.absolute_source());
+
+        Ok(())
     }
 
     pub fn outcome(
@@ -102,10 +326,18 @@ where
                 target: target.clone(),
                 error,
             })?;
+        let symlink = match self.file.action {
+            FileAction::Symlink => Some(self.link_source().map_err(|error| OutcomeError {
+                target: target.clone(),
+                error,
+            })?),
+            _ => None,
+        };
 
         Ok(Outcome {
             target,
             content,
+            symlink,
             permission: self
                 .file
                 .permission
@@ -168,6 +400,43 @@ fn relative_target_file<P: AsRef<Path>, E: Environment>(
     }
 }
 
+/// Failed render of a template on a machine that cannot decrypt the secrets.
+#[derive(Debug)]
+pub struct SecretsUnavailable {
+    pub error: Box<dyn Error>,
+    pub secrets_error: Box<dyn Error>,
+}
+
+impl Error for SecretsUnavailable {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+impl fmt::Display for SecretsUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (secrets are not available: {})",
+            self.error, self.secrets_error
+        )
+    }
+}
+
+/// Attach the reason the secrets are missing to a failed render, since it may have needed them.
+fn with_secrets_error(
+    rendered: Result<Vec<u8>, Box<dyn Error>>,
+    secrets: Result<&Secrets, Box<dyn Error>>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    match (rendered, secrets) {
+        (Err(error), Err(secrets_error)) => Err(Box::new(SecretsUnavailable {
+            error,
+            secrets_error,
+        })),
+        (rendered, _) => rendered,
+    }
+}
+
 fn source_file_from_target<P: AsRef<Path>>(target: P) -> (PathBuf, String) {
     let mut dir_path = PathBuf::new();
 
@@ -0,0 +1,31 @@
+use std::{fmt, path::PathBuf};
+
+/// Single step upgrading a repository to the current format version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Migration {
+    /// Version 0 tracked j2 files as `<source>.j2` while reading them from `<source>`
+    RenameTemplate {
+        dir_path: PathBuf,
+        source: String,
+    },
+    SetFormatVersion {
+        from: u32,
+        to: u32,
+    },
+}
+
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Migration::RenameTemplate { dir_path, source } => write!(
+                f,
+                "Rename template {} to {}.j2",
+                dir_path.join(source).to_string_lossy(),
+                source
+            ),
+            Migration::SetFormatVersion { from, to } => {
+                write!(f, "Set format version from {from} to {to}")
+            }
+        }
+    }
+}
@@ -1,18 +1,36 @@
 use std::marker::PhantomData;
 use std::rc::Rc;
-use std::{collections::HashMap, error::Error, fs, path::PathBuf};
-
-use crate::model::{DirectoryDescriptor, FileAction, Recipient, RootDescriptor, SecretKey};
-
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::model::{
+    DirectoryDescriptor, FileAction, FileDescriptor, Recipient, RepositoryVariables,
+    RootDescriptor, SecretKey, FORMAT_VERSION,
+};
+
+pub use self::backup::{Backup, BACKUPS_KEPT};
+pub use self::check::{CheckEntry, Issue};
 pub use self::environment::*;
 pub use self::file_ref::FileRef;
 use self::file_ref::RepositoryInfo;
+pub use self::migration::Migration;
 pub use self::outcome::{Changes, Outcome};
+pub use self::secrets::{Secrets, SECRETS_FILE};
 
 mod actions;
+pub mod atomic;
+mod backup;
+mod check;
+mod condition;
 mod environment;
 mod file_ref;
+mod migration;
 mod outcome;
+mod secrets;
 #[cfg(test)]
 mod tests;
 
@@ -41,6 +59,16 @@ where
         }
         let root: RootDescriptor = serde_json::from_reader(fs::File::open(&root_file)?)?;
 
+        if root.format_version > FORMAT_VERSION {
+            return Err(format!(
+                "Repository in directory {} has format version {}, this version of dotium only supports up to {}. Please upgrade dotium",
+                directory.to_string_lossy(),
+                root.format_version,
+                FORMAT_VERSION
+            )
+            .into());
+        }
+
         let mut dirs = HashMap::with_capacity(root.directories.len());
 
         for sub_directory in &root.directories {
@@ -53,11 +81,7 @@ where
         }
 
         Ok(Repository {
-            info: Rc::new(RepositoryInfo {
-                directory,
-                recipients: root.recipients.clone(),
-                phantom: PhantomData,
-            }),
+            info: Rc::new(RepositoryInfo::new(directory, &root)),
             root_file,
             root,
             dirs,
@@ -69,17 +93,16 @@ where
         let root_file = directory.join("dotium.json");
 
         let root = RootDescriptor {
+            format_version: FORMAT_VERSION,
             recipients: vec![recipient],
             recipient_requests: vec![],
             directories: vec![],
+            groups: BTreeMap::new(),
+            variables: RepositoryVariables::default(),
         };
 
         let repo = Repository {
-            info: Rc::new(RepositoryInfo {
-                directory,
-                recipients: root.recipients.clone(),
-                phantom: PhantomData,
-            }),
+            info: Rc::new(RepositoryInfo::new(directory, &root)),
             root_file,
             root,
             dirs: HashMap::new(),
@@ -90,20 +113,35 @@ where
         Ok(repo)
     }
 
+    /// Add targets to the repository, encrypted files may be restricted to some recipients.
+    ///
+    /// Files with a condition are only applied on machines matching it.
     pub fn add_files<I: IntoIterator<Item = PathBuf>>(
         &mut self,
         action: FileAction,
+        recipients: Option<Vec<String>>,
+        condition: Option<String>,
         targets: I,
     ) -> Result<Vec<FileRef<E>>, Box<dyn Error>> {
+        if recipients.is_some() && !action.is_crypted() {
+            return Err("Only encrypted files can be restricted to recipients".into());
+        }
+        if let Some(condition) = &condition {
+            condition::validate(condition)?;
+        }
+
         let mut added = Vec::new();
         for target in targets {
-            let file_ref = FileRef::new(self.info.clone(), target, action)?;
+            let mut file_ref = FileRef::new(self.info.clone(), target, action)?;
+            file_ref.file.recipients = recipients.clone();
+            file_ref.file.condition = condition.clone();
 
             if file_ref.absolute_source().exists() {
                 return Err(format!("{file_ref} already in repository").into());
             }
 
             actions::create_from_target(&self.info, &file_ref.dir_path, &file_ref.file)?;
+            self.info.record_change(file_ref.absolute_source());
 
             if let Some(dir) = self.dirs.get_mut(&file_ref.dir_path) {
                 dir.files.push(file_ref.file.clone());
@@ -124,6 +162,161 @@ where
         Ok(added)
     }
 
+    /// Remove files from the repository.
+    ///
+    /// The descriptors are stored before any source is deleted, so a failure part-way
+    /// never leaves descriptors pointing at removed sources.
+    pub fn remove_files(&mut self, files: &[FileRef<E>]) -> Result<(), Box<dyn Error>> {
+        let mut removed_dirs = Vec::new();
+
+        for file_ref in files {
+            let dir = self
+                .dirs
+                .get_mut(&file_ref.dir_path)
+                .ok_or_else(|| format!("{file_ref} not in repository"))?;
+            let count = dir.files.len();
+
+            dir.files.retain(|file| file != &file_ref.file);
+            if dir.files.len() == count {
+                return Err(format!("{file_ref} not in repository").into());
+            }
+            if dir.files.is_empty() {
+                self.dirs.remove(&file_ref.dir_path);
+                removed_dirs.push(file_ref.dir_path.clone());
+            }
+        }
+        self.root.directories = self.dirs.keys().cloned().collect();
+        self.root.directories.sort();
+        self.store()?;
+
+        for file_ref in files {
+            let source = file_ref.absolute_source();
+            if source.exists() {
+                fs::remove_file(&source)?;
+                self.info.record_change(source);
+            }
+        }
+        for dir_path in removed_dirs {
+            let dir_file = self.info.directory.join(&dir_path).join("dotium_dir.json");
+            if dir_file.exists() {
+                fs::remove_file(&dir_file)?;
+                self.info.record_change(dir_file);
+            }
+            self.prune_directory(&dir_path);
+        }
+
+        Ok(())
+    }
+
+    /// Move a tracked file to another action, re-encoding its source.
+    ///
+    /// The `.j2` suffix of the source is added or removed as needed. A permission that
+    /// was the default of the previous action is switched to the default of the new one.
+    /// If the source is renamed, the descriptors are stored before the old source is removed.
+    pub fn change_action(
+        &mut self,
+        file_ref: &FileRef<E>,
+        action: FileAction,
+        secret_keys: &[SecretKey],
+    ) -> Result<FileRef<E>, Box<dyn Error>> {
+        let content = file_ref.get_content(secret_keys)?;
+
+        let mut file = file_ref.file.clone();
+        let base_name = match file_ref.file.action {
+            FileAction::J2 => file.source.strip_suffix(".j2").unwrap_or(&file.source),
+            _ => &file.source,
+        };
+        file.source = match action {
+            FileAction::J2 => format!("{base_name}.j2"),
+            _ => base_name.to_string(),
+        };
+        file.action = action;
+        if !action.is_crypted() {
+            file.recipients = None;
+        }
+        if file.permission.as_ref() == Some(&file_ref.file.action.default_permission()) {
+            file.permission = Some(action.default_permission());
+        }
+
+        let converted = FileRef {
+            repository: self.info.clone(),
+            dir_path: file_ref.dir_path.clone(),
+            file,
+        };
+        let old_source = file_ref.absolute_source();
+        let new_source = converted.absolute_source();
+
+        if new_source != old_source && new_source.exists() {
+            return Err(format!("{converted} already in repository").into());
+        }
+
+        let descriptor = self.descriptor_mut(file_ref)?;
+
+        converted.set_content(&content)?;
+        *descriptor = converted.file.clone();
+
+        if new_source != old_source {
+            // Never leave a stored descriptor pointing at a removed source
+            self.store()?;
+            fs::remove_file(&old_source)?;
+            self.info.record_change(old_source);
+        }
+
+        Ok(converted)
+    }
+
+    /// Restrict an encrypted file to some recipients or groups (`None`: all recipients)
+    /// and re-encrypt it accordingly.
+    pub fn change_recipients(
+        &mut self,
+        file_ref: &FileRef<E>,
+        recipients: Option<Vec<String>>,
+        secret_keys: &[SecretKey],
+    ) -> Result<FileRef<E>, Box<dyn Error>> {
+        if !file_ref.file.action.is_crypted() {
+            return Err(format!("{file_ref} is not encrypted").into());
+        }
+        let content = file_ref.get_content(secret_keys)?;
+
+        let mut changed = FileRef {
+            repository: self.info.clone(),
+            dir_path: file_ref.dir_path.clone(),
+            file: file_ref.file.clone(),
+        };
+        changed.file.recipients = recipients;
+
+        let descriptor = self.descriptor_mut(file_ref)?;
+
+        changed.set_content(&content)?;
+        *descriptor = changed.file.clone();
+
+        Ok(changed)
+    }
+
+    fn descriptor_mut(
+        &mut self,
+        file_ref: &FileRef<E>,
+    ) -> Result<&mut FileDescriptor, Box<dyn Error>> {
+        self.dirs
+            .get_mut(&file_ref.dir_path)
+            .and_then(|dir| dir.files.iter_mut().find(|file| *file == &file_ref.file))
+            .ok_or_else(|| format!("{file_ref} not in repository").into())
+    }
+
+    /// Remove a directory and its parents from the repository as long as they are empty.
+    fn prune_directory(&self, dir_path: &Path) {
+        let mut current = Some(dir_path);
+
+        while let Some(dir_path) = current {
+            if dir_path.as_os_str().is_empty()
+                || fs::remove_dir(self.info.directory.join(dir_path)).is_err()
+            {
+                break;
+            }
+            current = dir_path.parent();
+        }
+    }
+
     pub fn directory(&self) -> PathBuf {
         self.info.directory.to_path_buf()
     }
@@ -132,6 +325,75 @@ where
         self.root.recipients.iter()
     }
 
+    pub fn groups(&self) -> impl Iterator<Item = (&String, &Vec<String>)> {
+        self.root.groups.iter()
+    }
+
+    pub fn variables(&self) -> &RepositoryVariables {
+        &self.root.variables
+    }
+
+    /// Set a repository variable, shared by all machines or only for a single recipient.
+    pub fn set_variable(&mut self, recipient: Option<&str>, key: &str, value: &str) {
+        let variables = match recipient {
+            Some(name) => self
+                .root
+                .variables
+                .recipients
+                .entry(name.to_string())
+                .or_default(),
+            None => &mut self.root.variables.shared,
+        };
+        variables.insert(key.to_string(), value.to_string());
+
+        self.refresh_info();
+    }
+
+    /// Remove a repository variable, returns whether it was set at all.
+    pub fn unset_variable(&mut self, recipient: Option<&str>, key: &str) -> bool {
+        let removed = match recipient {
+            Some(name) => {
+                let variables = &mut self.root.variables.recipients;
+                let removed = variables
+                    .get_mut(name)
+                    .is_some_and(|v| v.remove(key).is_some());
+
+                variables.retain(|_, v| !v.is_empty());
+                removed
+            }
+            None => self.root.variables.shared.remove(key).is_some(),
+        };
+
+        self.refresh_info();
+        removed
+    }
+
+    /// Decrypt the secret variables of the repository.
+    pub fn secrets(&self, secret_keys: &[SecretKey]) -> Result<Secrets, Box<dyn Error>> {
+        secrets::read(&self.info.directory, secret_keys)
+    }
+
+    /// Whether the secret variables are not encrypted for the current recipients.
+    pub fn secrets_stale(&self) -> Result<bool, Box<dyn Error>> {
+        secrets::is_stale(
+            &self.info.directory,
+            &self.root.recipients.iter().collect::<Vec<&Recipient>>(),
+        )
+    }
+
+    /// Encrypt the secret variables of the repository for all recipients.
+    pub fn store_secrets(&self, secrets: &Secrets) -> Result<(), Box<dyn Error>> {
+        secrets::write(
+            &self.info.directory,
+            &self.root.recipients.iter().collect::<Vec<&Recipient>>(),
+            secrets,
+        )?;
+        self.info
+            .record_change(self.info.directory.join(secrets::SECRETS_FILE));
+
+        Ok(())
+    }
+
     pub fn recipient_requests(&self) -> impl Iterator<Item = &Recipient> {
         self.root.recipient_requests.iter()
     }
@@ -150,6 +412,16 @@ where
         self.root.recipient_requests.push(recipient);
     }
 
+    /// Remove a pending recipient request (by name or key) for good.
+    pub fn reject_recipient_request(
+        &mut self,
+        name_or_key: &str,
+    ) -> Result<Recipient, Box<dyn Error>> {
+        let index = find_recipient(&self.root.recipient_requests, name_or_key)?;
+
+        Ok(self.root.recipient_requests.remove(index))
+    }
+
     pub fn approve_recipients(
         &mut self,
         approved: &[Recipient],
@@ -165,19 +437,283 @@ where
             }
         }
         self.root.recipient_requests = remaining_requests;
-        self.info = Rc::new(RepositoryInfo {
-            directory: self.info.directory.clone(),
-            recipients: self.root.recipients.clone(),
-            phantom: PhantomData,
-        });
 
+        self.reencrypt(secret_keys)?;
+        self.store()
+    }
+
+    /// Remove a recipient (by name or key) and re-encrypt all files for the remaining ones.
+    pub fn revoke_recipient(
+        &mut self,
+        name_or_key: &str,
+        secret_keys: &[SecretKey],
+    ) -> Result<Recipient, Box<dyn Error>> {
+        let index = find_recipient(&self.root.recipients, name_or_key)?;
+
+        if self.root.recipients.len() == 1 {
+            return Err("Cannot revoke the last recipient of a repository".into());
+        }
+
+        let mut recipients = self.root.recipients.clone();
+        let revoked = recipients.remove(index);
+        let mut groups = self.root.groups.clone();
+        let mut dropped = HashSet::new();
+
+        // Drop the name from groups and restricted files, unless another recipient shares it.
+        // Groups without members are dropped as well, like with `set_group`.
+        let name_left = recipients.iter().any(|r| r.name == revoked.name);
+        if !name_left {
+            for (group, members) in &mut groups {
+                members.retain(|name| name != &revoked.name);
+                if members.is_empty() {
+                    dropped.insert(group.clone());
+                }
+            }
+            groups.retain(|group, _| !dropped.contains(group));
+        }
+        let keep_in_scope = |name: &String| {
+            (name_left || name != &revoked.name || groups.contains_key(name))
+                && !dropped.contains(name)
+        };
+        let is_recipient = |name: &String| recipients.iter().any(|r| &r.name == name);
+        let has_recipients = |name: &String| {
+            is_recipient(name)
+                || groups
+                    .get(name)
+                    .is_some_and(|members| members.iter().any(is_recipient))
+        };
+
+        let mut orphaned = self
+            .dirs
+            .values()
+            .flat_map(|dir| &dir.files)
+            .filter(|file| file.action.is_crypted())
+            .filter(|file| {
+                file.recipients.as_ref().is_some_and(|scope| {
+                    !scope
+                        .iter()
+                        .filter(|name| keep_in_scope(name))
+                        .any(has_recipients)
+                })
+            })
+            .map(|file| file.target.to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        if !orphaned.is_empty() {
+            orphaned.sort();
+            return Err(format!(
+                "Revoking {} leaves no recipients for {}. Use 'dotium recipients scope' to change their recipients first",
+                revoked.name,
+                orphaned.join(", ")
+            )
+            .into());
+        }
+
+        for dir in self.dirs.values_mut() {
+            for file in &mut dir.files {
+                if let Some(scope) = file.recipients.as_mut() {
+                    scope.retain(keep_in_scope);
+                }
+            }
+        }
+        self.root.recipients = recipients;
+        self.root.groups = groups;
+
+        self.reencrypt(secret_keys)?;
+        self.store()?;
+
+        Ok(revoked)
+    }
+
+    /// Set the recipient names of a group, an empty list removes the group.
+    ///
+    /// Encrypted files restricted to the group are re-encrypted for its new members.
+    pub fn set_group(
+        &mut self,
+        group: &str,
+        members: Vec<String>,
+        secret_keys: &[SecretKey],
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(unknown) = members
+            .iter()
+            .find(|name| !self.root.recipients.iter().any(|r| &r.name == *name))
+        {
+            return Err(format!("No recipient {unknown} found").into());
+        }
+
+        if members.is_empty() {
+            self.root.groups.remove(group);
+        } else {
+            self.root.groups.insert(group.to_string(), members);
+        }
+
+        self.reencrypt(secret_keys)?;
+        self.store()
+    }
+
+    /// Re-encrypt the files (and secrets) whose recipients changed since the info was created.
+    fn reencrypt(&mut self, secret_keys: &[SecretKey]) -> Result<(), Box<dyn Error>> {
+        let previous = self.refresh_info();
+
+        let mut changed = Vec::new();
         for file in self.files() {
-            let content = file.get_content(secret_keys)?;
+            if !file.file.action.is_crypted() {
+                continue;
+            }
+            let new_keys = recipient_keys(self.info.recipients_for(&file.file)?);
+            let old_keys = previous
+                .recipients_for(&file.file)
+                .map(recipient_keys)
+                .unwrap_or_default();
+
+            // Scopes changed along with the recipients only show up in the encrypted file
+            if new_keys != old_keys || file.is_stale().unwrap_or(false) {
+                changed.push(file);
+            }
+        }
+
+        let recipients_changed = recipient_keys(previous.recipients.iter().collect())
+            != recipient_keys(self.info.recipients.iter().collect());
+
+        self.reencrypt_files(&changed, recipients_changed, secret_keys)
+    }
+
+    /// Encrypt files (and optionally the secrets) again for their current recipients.
+    ///
+    /// All files are decrypted before the first one is written, so that a file that
+    /// cannot be re-encrypted leaves the repository untouched.
+    pub fn reencrypt_files(
+        &self,
+        files: &[FileRef<E>],
+        include_secrets: bool,
+        secret_keys: &[SecretKey],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut contents = Vec::with_capacity(files.len());
+        for file in files {
+            let content = file
+                .get_content(secret_keys)
+                .map_err(|error| format!("Cannot re-encrypt {file}: {error}"))?;
+
+            contents.push(content);
+        }
+        let secrets = match include_secrets {
+            true => Some(
+                self.secrets(secret_keys)
+                    .map_err(|error| format!("Cannot re-encrypt secrets: {error}"))?,
+            ),
+            false => None,
+        };
 
+        for (file, content) in files.iter().zip(contents) {
             file.set_content(&content)?;
         }
+        if let Some(secrets) = secrets {
+            self.store_secrets(&secrets)?;
+        }
 
-        self.store()
+        Ok(())
+    }
+
+    /// Update the info shared with all file references after the root descriptor changed.
+    fn refresh_info(&mut self) -> Rc<RepositoryInfo<E>> {
+        let mut info = RepositoryInfo::new(self.info.directory.clone(), &self.root);
+        info.changes = self.info.changes.clone();
+        let info = Rc::new(info);
+
+        std::mem::replace(&mut self.info, info)
+    }
+
+    /// Validate the repository without touching the home directory.
+    pub fn check(
+        &self,
+        secret_keys: Option<&[SecretKey]>,
+    ) -> Result<Vec<CheckEntry>, Box<dyn Error>> {
+        check::check(self, secret_keys)
+    }
+
+    /// Files of the repository (relative paths) written or removed since it was opened.
+    pub fn changed_files(&self) -> Vec<PathBuf> {
+        self.info
+            .changes
+            .borrow()
+            .iter()
+            .filter_map(|path| path.strip_prefix(&self.info.directory).ok())
+            .map(Path::to_path_buf)
+            .collect()
+    }
+
+    pub fn format_version(&self) -> u32 {
+        self.root.format_version
+    }
+
+    /// Steps necessary to upgrade the repository to the current format version.
+    pub fn migrations(&self) -> Result<Vec<Migration>, Box<dyn Error>> {
+        let mut migrations = Vec::new();
+
+        if self.root.format_version < 1 {
+            let mut dir_paths = self.dirs.keys().collect::<Vec<&PathBuf>>();
+            dir_paths.sort();
+
+            for dir_path in dir_paths {
+                for file in &self.dirs[dir_path].files {
+                    if file.action != FileAction::J2 || file.source.ends_with(".j2") {
+                        continue;
+                    }
+                    let directory = self.info.directory.join(dir_path);
+                    let old_source = directory.join(&file.source);
+                    let new_source = directory.join(format!("{}.j2", file.source));
+
+                    if old_source.exists() && new_source.exists() {
+                        return Err(format!(
+                            "Both {} and {} exist, remove the outdated one first",
+                            old_source.to_string_lossy(),
+                            new_source.to_string_lossy()
+                        )
+                        .into());
+                    }
+                    migrations.push(Migration::RenameTemplate {
+                        dir_path: dir_path.clone(),
+                        source: file.source.clone(),
+                    });
+                }
+            }
+        }
+        if self.root.format_version < FORMAT_VERSION {
+            migrations.push(Migration::SetFormatVersion {
+                from: self.root.format_version,
+                to: FORMAT_VERSION,
+            });
+        }
+
+        Ok(migrations)
+    }
+
+    /// Apply the steps of `migrations()`, descriptors have to be stored afterwards.
+    pub fn migrate(&mut self, migrations: &[Migration]) -> Result<(), Box<dyn Error>> {
+        for migration in migrations {
+            match migration {
+                Migration::RenameTemplate { dir_path, source } => {
+                    let directory = self.info.directory.join(dir_path);
+                    let new_source = format!("{source}.j2");
+
+                    // Sources written by `track` already have the suffix
+                    if directory.join(source).exists() {
+                        fs::rename(directory.join(source), directory.join(&new_source))?;
+                        self.info.record_change(directory.join(source));
+                        self.info.record_change(directory.join(&new_source));
+                    }
+                    if let Some(file) = self
+                        .dirs
+                        .get_mut(dir_path)
+                        .and_then(|dir| dir.files.iter_mut().find(|f| &f.source == source))
+                    {
+                        file.source = new_source;
+                    }
+                }
+                Migration::SetFormatVersion { to, .. } => self.root.format_version = *to,
+            }
+        }
+
+        Ok(())
     }
 
     pub fn store(&self) -> Result<(), Box<dyn Error>> {
@@ -188,6 +724,7 @@ where
             .open(&self.root_file)?;
 
         serde_json::to_writer_pretty(root_file, &self.root)?;
+        self.info.record_change(&self.root_file);
 
         for (dir_path, dir) in &self.dirs {
             let dir_path = self.info.directory.join(dir_path);
@@ -200,8 +737,34 @@ where
                 .open(dir_path.join("dotium_dir.json"))?;
 
             serde_json::to_writer_pretty(dir_file, dir)?;
+            self.info.record_change(dir_path.join("dotium_dir.json"));
         }
 
         Ok(())
     }
 }
+
+fn recipient_keys(recipients: Vec<&Recipient>) -> Vec<&str> {
+    let mut keys = recipients
+        .into_iter()
+        .map(|r| r.key.as_str())
+        .collect::<Vec<&str>>();
+    keys.sort();
+
+    keys
+}
+
+fn find_recipient(recipients: &[Recipient], name_or_key: &str) -> Result<usize, Box<dyn Error>> {
+    let matching = recipients
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| r.name == name_or_key || r.key == name_or_key)
+        .map(|(index, _)| index)
+        .collect::<Vec<usize>>();
+
+    match matching[..] {
+        [index] => Ok(index),
+        [] => Err(format!("No recipient {name_or_key} found").into()),
+        _ => Err(format!("Recipient {name_or_key} is ambiguous, use the key instead").into()),
+    }
+}
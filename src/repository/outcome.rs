@@ -1,18 +1,23 @@
 use std::{error::Error, fmt, fs, marker::PhantomData, path::PathBuf};
 
-use super::Environment;
+use super::{atomic, Backup, Environment};
 
 #[derive(Debug)]
 pub enum Changes {
     NewFile,
     Diff(Vec<u8>),
     ChangePermission(String),
+    /// Target of a symlink file is a regular file (`None`) or a symlink pointing elsewhere
+    Relink(Option<PathBuf>),
     None,
 }
+
 #[derive(Debug)]
 pub struct Outcome<E> {
     pub target: PathBuf,
     pub content: Vec<u8>,
+    /// Target is supposed to be a symlink to this file
+    pub symlink: Option<PathBuf>,
     pub permission: String,
     pub phantom: PhantomData<E>,
 }
@@ -22,6 +27,22 @@ where
     E: Environment,
 {
     pub fn changes(&self) -> Result<Changes, Box<dyn Error>> {
+        if let Some(source) = &self.symlink {
+            return match fs::symlink_metadata(&self.target) {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    let current = fs::read_link(&self.target)?;
+
+                    if &current == source {
+                        Ok(Changes::None)
+                    } else {
+                        Ok(Changes::Relink(Some(current)))
+                    }
+                }
+                Ok(_) => Ok(Changes::Relink(None)),
+                Err(_) => Ok(Changes::NewFile),
+            };
+        }
+
         if self.target.exists() {
             let current_content = fs::read(&self.target)?;
 
@@ -42,18 +63,31 @@ where
         }
     }
 
-    pub fn apply(&self) -> Result<(), Box<dyn Error>> {
-        if let Some(parent) = self.target.parent() {
-            fs::create_dir_all(parent)?;
+    pub fn apply(&self, backup: &mut Backup<E>) -> Result<(), Box<dyn Error>> {
+        if let Some(source) = &self.symlink {
+            backup.save(&self.target)?;
+
+            if let Some(parent) = self.target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            return atomic::write_symlink(&self.target, source);
         }
 
-        fs::write(&self.target, &self.content)?;
+        // Symlinks at the target are kept, so the backup is of the file that is written
+        let target = atomic::resolve_symlinks(&self.target)?;
 
-        if let Some(permissions) = E::permission_from_string(&self.permission) {
-            fs::set_permissions(&self.target, permissions)?;
+        backup.save(&target)?;
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
         }
 
-        Ok(())
+        atomic::write_file(
+            &target,
+            &self.content,
+            E::permission_from_string(&self.permission),
+        )
     }
 }
 
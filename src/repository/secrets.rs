@@ -0,0 +1,52 @@
+use std::{collections::BTreeMap, error::Error, fs, path::Path};
+
+use crate::model::{Recipient, SecretKey};
+
+use super::actions::{decrypt_file, encrypt_file, is_encrypted_for};
+
+/// File in the repository root containing the encrypted secret variables
+pub const SECRETS_FILE: &str = "secrets.age";
+
+/// Secret variables by name
+pub type Secrets = BTreeMap<String, String>;
+
+/// Decrypt the secrets of a repository, there are none if the file does not exist.
+pub fn read(directory: &Path, secret_keys: &[SecretKey]) -> Result<Secrets, Box<dyn Error>> {
+    let secrets_file = directory.join(SECRETS_FILE);
+
+    if !secrets_file.is_file() {
+        return Ok(Secrets::new());
+    }
+    let content = decrypt_file(&secrets_file, secret_keys)?;
+
+    Ok(serde_json::from_slice(&content)?)
+}
+
+/// Whether the secrets file exists and is not encrypted for the recipients.
+pub fn is_stale(directory: &Path, recipients: &[&Recipient]) -> Result<bool, Box<dyn Error>> {
+    let secrets_file = directory.join(SECRETS_FILE);
+
+    Ok(secrets_file.is_file() && !is_encrypted_for(&secrets_file, recipients)?)
+}
+
+/// Encrypt the secrets of a repository for the recipients, the file is removed if there are none.
+pub fn write(
+    directory: &Path,
+    recipients: &[&Recipient],
+    secrets: &Secrets,
+) -> Result<(), Box<dyn Error>> {
+    let secrets_file = directory.join(SECRETS_FILE);
+
+    if secrets.is_empty() {
+        if secrets_file.exists() {
+            fs::remove_file(secrets_file)?;
+        }
+        return Ok(());
+    }
+
+    encrypt_file(
+        &secrets_file,
+        recipients,
+        &serde_json::to_vec_pretty(secrets)?,
+    )
+}
@@ -1,11 +1,19 @@
-use std::{collections::HashMap, error::Error, path::PathBuf};
+use std::{collections::HashMap, error::Error, fs, marker::PhantomData, path::PathBuf};
+
+use age::secrecy::{ExposeSecret, SecretString};
 
 use crate::{
-    model::{FileAction, MachineContext, SecretKey},
+    model::{
+        is_protected, lookup_passphrase, protect_keys, unprotect_keys, FileAction, MachineContext,
+        SecretKey, SystemContext, XdgDirs, ASKPASS_ENV, FORMAT_VERSION, PASSPHRASE_ENV,
+    },
     repository::outcome::OutcomeError,
 };
 
-use super::{Environment, Outcome, Repository};
+use super::{
+    file_ref::SecretsUnavailable, secrets::SECRETS_FILE, Backup, Changes, Environment, Issue,
+    Outcome, Repository, Secrets,
+};
 
 struct TestEnvironment {}
 
@@ -24,8 +32,26 @@ impl Environment for TestEnvironment {
             .join("dotium"))
     }
 
+    fn system() -> Result<SystemContext, Box<dyn Error>> {
+        Ok(SystemContext {
+            hostname: "testhost".to_string(),
+            os: "testos".to_string(),
+            distribution: Some("testdistro".to_string()),
+            arch: "testarch".to_string(),
+            username: Some("tester".to_string()),
+            uid: Some(1234),
+            home: Self::home_dir()?,
+            xdg: XdgDirs {
+                config_home: Some(Self::home_dir()?.join(".config")),
+                data_home: None,
+                cache_home: None,
+                state_home: None,
+            },
+        })
+    }
+
     fn permission_to_string(_: std::fs::Permissions) -> String {
-        Default::default()
+        "0644".to_string()
     }
 
     fn permission_from_string(_: &str) -> Option<std::fs::Permissions> {
@@ -49,6 +75,8 @@ fn track_regular_files() -> Result<(), Box<dyn Error>> {
 
     repository.add_files(
         FileAction::AsIs,
+        None,
+        None,
         vec![PathBuf::from(".config/someapp/config")],
     )?;
     repository.store()?;
@@ -89,6 +117,8 @@ fn track_secret_files() -> Result<(), Box<dyn Error>> {
 
     repository.add_files(
         FileAction::Crypted,
+        None,
+        None,
         vec![PathBuf::from(".config/someotherapp/secret_config")],
     )?;
     repository.store()?;
@@ -112,3 +142,960 @@ fn track_secret_files() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[test]
+fn backup_and_restore() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = tempfile::tempdir()?;
+    let backups_dir = tmp_dir.path().join("backups");
+    let existing = tmp_dir.path().join("existing");
+    let created = tmp_dir.path().join("created");
+
+    fs::write(&existing, b"original")?;
+
+    let mut backup = Backup::<TestEnvironment>::new(&backups_dir);
+    backup.save(&existing)?;
+    backup.save(&created)?;
+    fs::write(&existing, b"overwritten")?;
+    fs::write(&created, b"new")?;
+    // Only the first state of a target is kept
+    backup.save(&existing)?;
+
+    let backups = Backup::<TestEnvironment>::list(&backups_dir)?;
+    assert_eq!(backups.len(), 1);
+    assert_eq!(backups[0].descriptor.entries.len(), 2);
+
+    let mut current = Backup::<TestEnvironment>::new(tmp_dir.path().join("current"));
+    for entry in &backups[0].descriptor.entries {
+        backups[0].restore(entry, &mut current)?;
+    }
+
+    assert_eq!(fs::read(&existing)?, b"original");
+    assert!(!created.exists());
+    assert_eq!(current.descriptor.entries.len(), 2);
+
+    // Only regular files are removed, not what the user put there in the meantime
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink("existing", &created)?;
+        backups[0].restore(&backups[0].descriptor.entries[1], &mut current)?;
+        assert!(fs::symlink_metadata(&created)?.file_type().is_symlink());
+    }
+
+    let mut older = Backup::<TestEnvironment>::new(&backups_dir);
+    older.id = "00000000-000000-000".to_string();
+    older.directory = backups_dir.join(&older.id);
+    older.save(&existing)?;
+    assert_eq!(Backup::<TestEnvironment>::list(&backups_dir)?.len(), 2);
+    assert_eq!(
+        Backup::<TestEnvironment>::prune(&backups_dir, 1)?,
+        vec![older.id]
+    );
+    assert_eq!(Backup::<TestEnvironment>::list(&backups_dir)?.len(), 1);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn apply_keeps_symlinks() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = tempfile::tempdir()?;
+    let real_file = tmp_dir.path().join("real");
+    let link = tmp_dir.path().join("link");
+
+    fs::write(&real_file, b"old")?;
+    std::os::unix::fs::symlink("real", &link)?;
+
+    let outcome = Outcome::<TestEnvironment> {
+        target: link.clone(),
+        content: b"new".to_vec(),
+        symlink: None,
+        permission: "0600".to_string(),
+        phantom: PhantomData,
+    };
+    let mut backup = Backup::new(tmp_dir.path().join("backups"));
+
+    outcome.apply(&mut backup)?;
+
+    assert!(fs::symlink_metadata(&link)?.file_type().is_symlink());
+    assert_eq!(fs::read(&real_file)?, b"new");
+    assert_eq!(fs::read_dir(tmp_dir.path())?.count(), 3);
+    assert!(backup.entry(&link).is_none());
+    assert!(backup.entry(&real_file).is_some());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn apply_symlink() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = tempfile::tempdir()?;
+    let source = tmp_dir.path().join("source");
+    let target = tmp_dir.path().join("target");
+
+    fs::write(&source, b"content")?;
+    fs::write(&target, b"content")?;
+
+    let outcome = Outcome::<TestEnvironment> {
+        target: target.clone(),
+        content: b"content".to_vec(),
+        symlink: Some(source.clone()),
+        permission: "0644".to_string(),
+        phantom: PhantomData,
+    };
+    let mut backup = Backup::new(tmp_dir.path().join("backups"));
+
+    assert!(matches!(outcome.changes()?, Changes::Relink(None)));
+
+    outcome.apply(&mut backup)?;
+
+    assert_eq!(fs::read_link(&target)?, source);
+    assert!(matches!(outcome.changes()?, Changes::None));
+
+    // Restore replaces the link instead of writing through it
+    fs::write(&source, b"edited")?;
+    let entry = backup.entry(&target).ok_or("backup entry missing")?.clone();
+    let mut current = Backup::<TestEnvironment>::new(tmp_dir.path().join("current"));
+    backup.restore(&entry, &mut current)?;
+
+    assert!(fs::symlink_metadata(&target)?.is_file());
+    assert_eq!(fs::read(&target)?, b"content");
+    assert_eq!(fs::read(&source)?, b"edited");
+
+    let entry = current
+        .entry(&target)
+        .ok_or("backup entry missing")?
+        .clone();
+    current.restore(&entry, &mut Backup::new(tmp_dir.path().join("other")))?;
+    assert_eq!(fs::read_link(&target)?, source);
+
+    Ok(())
+}
+
+#[test]
+fn untrack_files() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let secret_key = SecretKey::generate();
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_key.as_recipient("Test"),
+    )?;
+
+    let added = repository.add_files(
+        FileAction::AsIs,
+        None,
+        None,
+        vec![
+            PathBuf::from(".config/someapp/config"),
+            PathBuf::from(".config/someotherapp/secret_config"),
+        ],
+    )?;
+    repository.store()?;
+
+    repository.remove_files(&added[0..1])?;
+    repository.store()?;
+
+    let repository = Repository::<TestEnvironment>::open(tmp_repo.path())?;
+    let files = repository.files().collect::<Vec<_>>();
+
+    assert_eq!(files.len(), 1);
+    assert_eq!(
+        files[0].file.target,
+        PathBuf::from(".config/someotherapp/secret_config")
+    );
+    assert!(!tmp_repo.path().join("config/someapp").exists());
+    assert!(TestEnvironment::home_dir()?
+        .join(".config/someapp/config")
+        .exists());
+
+    Ok(())
+}
+
+#[test]
+fn change_action() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let secret_key = SecretKey::generate();
+    let context = MachineContext {
+        recipient: secret_key.as_recipient("test"),
+        variables: HashMap::new(),
+    };
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_key.as_recipient("Test"),
+    )?;
+
+    let added = repository.add_files(
+        FileAction::AsIs,
+        None,
+        None,
+        vec![PathBuf::from(".config/someapp/config")],
+    )?;
+    let secret_keys = &[secret_key];
+
+    let crypted = repository.change_action(&added[0], FileAction::Crypted, secret_keys)?;
+    assert_ne!(
+        fs::read(crypted.absolute_source())?,
+        fs::read(TestEnvironment::home_dir()?.join(".config/someapp/config"))?
+    );
+
+    let template = repository.change_action(&crypted, FileAction::J2, secret_keys)?;
+    assert_eq!(template.file.source, "config.j2");
+    assert!(!crypted.absolute_source().exists());
+    // The descriptor on disk already points at the new source
+    let stored = Repository::<TestEnvironment>::open(tmp_repo.path())?;
+    assert!(stored.files().all(|f| f.file.source == "config.j2"));
+
+    let outcomes = repository
+        .files()
+        .map(|f| f.outcome(&context, secret_keys))
+        .collect::<Result<Vec<Outcome<_>>, OutcomeError>>()?;
+
+    assert_eq!(outcomes.len(), 1);
+    assert_eq!(
+        outcomes[0].content,
+        b"This is some\nfancy \nconfig file\nthat\ndoes not\nrequire\nprotection\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn reject_recipient_request() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let secret_key = SecretKey::generate();
+    let laptop = SecretKey::generate().as_recipient("laptop");
+    let desktop = SecretKey::generate().as_recipient("desktop");
+    let other_desktop = SecretKey::generate().as_recipient("desktop");
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_key.as_recipient("Test"),
+    )?;
+    repository.add_recipient_request(laptop.clone());
+    repository.add_recipient_request(desktop.clone());
+    repository.add_recipient_request(other_desktop.clone());
+
+    assert_eq!(repository.reject_recipient_request("laptop")?, laptop);
+    assert!(repository.reject_recipient_request("laptop").is_err());
+
+    let error = repository
+        .reject_recipient_request("desktop")
+        .expect_err("name is ambiguous");
+    assert!(error.to_string().contains("ambiguous"));
+    assert_eq!(repository.recipient_requests().count(), 2);
+
+    assert_eq!(
+        repository.reject_recipient_request(&other_desktop.key)?,
+        other_desktop
+    );
+    assert_eq!(
+        repository.recipient_requests().cloned().collect::<Vec<_>>(),
+        vec![desktop]
+    );
+    assert_eq!(repository.recipients().count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn revoke_recipient() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let secret_key = SecretKey::generate();
+    let other_key = SecretKey::generate();
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_key.as_recipient("Test"),
+    )?;
+    repository.add_recipient_request(other_key.as_recipient("Other"));
+    repository.approve_recipients(&[other_key.as_recipient("Other")], &[])?;
+
+    repository.add_files(
+        FileAction::Crypted,
+        None,
+        None,
+        vec![PathBuf::from(".config/someotherapp/secret_config")],
+    )?;
+    repository.store()?;
+
+    let other_keys = [other_key];
+    for file in repository.files() {
+        file.get_content(&other_keys)?;
+    }
+
+    // Files only the revoked recipient could read have to be rescoped first
+    repository.set_group("others", vec!["Other".to_string()], &[])?;
+    let scoped = repository.add_files(
+        FileAction::Crypted,
+        Some(vec!["others".to_string()]),
+        None,
+        vec![PathBuf::from(".config/someapp/config")],
+    )?;
+    let error = repository
+        .revoke_recipient("Other", &[])
+        .expect_err("file without recipients");
+    assert!(error.to_string().contains(".config/someapp/config"));
+    assert_eq!(repository.recipients().count(), 2);
+    repository.change_recipients(
+        &scoped[0],
+        Some(vec!["others".to_string(), "Test".to_string()]),
+        &other_keys,
+    )?;
+
+    let revoked = repository.revoke_recipient("Other", &[secret_key])?;
+    assert_eq!(revoked.name, "Other");
+    assert_eq!(repository.recipients().count(), 1);
+    assert!(repository.root.groups.is_empty());
+    assert!(repository
+        .dirs
+        .values()
+        .flat_map(|dir| &dir.files)
+        .all(|file| file
+            .recipients
+            .as_ref()
+            .is_none_or(|scope| scope == &["Test"])));
+    assert!(repository.revoke_recipient("Test", &[]).is_err());
+
+    for file in repository.files() {
+        assert!(file.get_content(&other_keys).is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn track_secret_files_with_ssh_key() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let key_file = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join("keys")
+        .join("id_ed25519");
+    let secret_keys = SecretKey::read_from(fs::File::open(&key_file)?, None)?;
+    let context = MachineContext {
+        recipient: secret_keys[0].as_recipient("test"),
+        variables: HashMap::new(),
+    };
+
+    assert_eq!(secret_keys.len(), 1);
+    assert!(context.recipient.is_ssh());
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_keys[0].as_recipient("Test"),
+    )?;
+
+    repository.add_files(
+        FileAction::Crypted,
+        None,
+        None,
+        vec![PathBuf::from(".config/someotherapp/secret_config")],
+    )?;
+    repository.store()?;
+
+    let outcomes = repository
+        .files()
+        .map(|f| f.outcome(&context, &secret_keys))
+        .collect::<Result<Vec<Outcome<_>>, OutcomeError>>()?;
+
+    assert_eq!(outcomes.len(), 1);
+    assert!(outcomes[0]
+        .content
+        .starts_with(b"This is\na very\nsecret config\n"));
+
+    Ok(())
+}
+
+#[test]
+fn read_protected_keys() -> Result<(), Box<dyn Error>> {
+    let secret_key = SecretKey::generate();
+    let mut keys = Vec::new();
+    secret_key.write_to(&mut keys)?;
+
+    let protected = protect_keys(&keys, SecretString::from("passphrase".to_string()))?;
+
+    assert!(is_protected(&protected));
+    assert!(!is_protected(&keys));
+
+    assert!(unprotect_keys(&protected, SecretString::from("wrong".to_string())).is_err());
+    let content = unprotect_keys(&protected, SecretString::from("passphrase".to_string()))?;
+    let secret_keys = SecretKey::read_from(content.as_slice(), None)?;
+
+    assert_eq!(secret_keys.len(), 1);
+    assert_eq!(secret_keys[0].public_key(), secret_key.public_key());
+
+    Ok(())
+}
+
+#[test]
+fn lookup_passphrase_variables() -> Result<(), Box<dyn Error>> {
+    assert!(lookup_passphrase("Passphrase", |_| None)?.is_none());
+
+    let passphrase = lookup_passphrase("Passphrase", |name| {
+        (name == PASSPHRASE_ENV).then(|| "secret".to_string())
+    })?
+    .ok_or("passphrase missing")?;
+    assert_eq!(passphrase.expose_secret(), "secret");
+
+    // The askpass program gets the prompt as argument, so echo answers with the prompt
+    let passphrase = lookup_passphrase("Passphrase", |name| {
+        (name == ASKPASS_ENV).then(|| "echo".to_string())
+    })?
+    .ok_or("passphrase missing")?;
+    assert_eq!(passphrase.expose_secret(), "Passphrase");
+
+    Ok(())
+}
+
+#[test]
+fn restrict_secret_files() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let laptop_keys = [SecretKey::generate()];
+    let ci_keys = [SecretKey::generate()];
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        laptop_keys[0].as_recipient("laptop"),
+    )?;
+    let ci = ci_keys[0].as_recipient("ci");
+    repository.add_recipient_request(ci.clone());
+    repository.approve_recipients(&[ci], &laptop_keys)?;
+
+    repository.add_files(
+        FileAction::Crypted,
+        Some(vec!["laptop".to_string()]),
+        None,
+        vec![PathBuf::from(".config/someotherapp/secret_config")],
+    )?;
+    repository.store()?;
+
+    let file_ref = repository.files().next().ok_or("file missing")?;
+
+    assert!(!file_ref.is_excluded(&laptop_keys));
+    assert!(file_ref.is_excluded(&ci_keys));
+    assert!(file_ref.get_content(&laptop_keys).is_ok());
+    assert!(file_ref.get_content(&ci_keys).is_err());
+
+    // Only files whose recipients change have to be decrypted
+    let other = SecretKey::generate().as_recipient("other");
+    repository.add_recipient_request(other.clone());
+    repository.approve_recipients(&[other], &ci_keys)?;
+
+    repository.set_group(
+        "machines",
+        vec!["laptop".to_string(), "ci".to_string()],
+        &laptop_keys,
+    )?;
+    let file_ref = repository.change_recipients(
+        &file_ref,
+        Some(vec!["machines".to_string()]),
+        &laptop_keys,
+    )?;
+    repository.store()?;
+
+    assert!(!file_ref.is_excluded(&ci_keys));
+    assert!(file_ref.get_content(&ci_keys).is_ok());
+
+    assert!(repository
+        .add_files(
+            FileAction::AsIs,
+            Some(vec!["ci".to_string()]),
+            None,
+            vec![PathBuf::from(".config/someapp/config")],
+        )
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn conditional_files() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let secret_key = SecretKey::generate();
+    let mut context = MachineContext {
+        recipient: secret_key.as_recipient("laptop"),
+        variables: HashMap::from([("work".to_string(), "true".to_string())]),
+    };
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_key.as_recipient("Test"),
+    )?;
+
+    assert!(repository
+        .add_files(
+            FileAction::AsIs,
+            None,
+            Some("os ==".to_string()),
+            vec![PathBuf::from(".config/someapp/config")],
+        )
+        .is_err());
+
+    let added = repository.add_files(
+        FileAction::AsIs,
+        None,
+        Some(
+            "os == \"testos\" and recipient == \"laptop\" and machine.work == \"true\"".to_string(),
+        ),
+        vec![PathBuf::from(".config/someapp/config")],
+    )?;
+
+    assert!(added[0].is_applicable(&context)?);
+
+    context.recipient.name = "desktop".to_string();
+    assert!(!added[0].is_applicable(&context)?);
+
+    context.recipient.name = "laptop".to_string();
+    context.variables.clear();
+    assert!(added[0].is_applicable(&context).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn repository_variables() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let secret_keys = [SecretKey::generate()];
+    let context = MachineContext {
+        recipient: secret_keys[0].as_recipient("laptop"),
+        variables: HashMap::from([("email".to_string(), "me@home".to_string())]),
+    };
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_keys[0].as_recipient("laptop"),
+    )?;
+    repository.set_variable(None, "proxy", "proxy.corp");
+    repository.set_variable(None, "email", "me@corp");
+    repository.set_variable(Some("laptop"), "proxy", "proxy.laptop");
+    repository.set_variable(Some("desktop"), "proxy", "proxy.desktop");
+
+    let added = repository.add_files(
+        FileAction::AsIs,
+        None,
+        None,
+        vec![PathBuf::from(".config/someapp/config")],
+    )?;
+    let file_ref = repository.change_action(&added[0], FileAction::J2, &secret_keys)?;
+    file_ref.set_content(
+        b"{{ variables.proxy }} {{ variables.email }} {{ repository.email }} {{ machine.email }}",
+    )?;
+
+    assert_eq!(
+        file_ref.get_rendered(&context, &secret_keys)?,
+        b"proxy.laptop me@home me@corp me@home"
+    );
+
+    assert!(repository.unset_variable(Some("laptop"), "proxy"));
+    assert!(!repository.unset_variable(Some("laptop"), "proxy"));
+    let file_ref = repository.files().next().ok_or("file missing")?;
+
+    assert_eq!(
+        file_ref.get_rendered(&context, &secret_keys)?,
+        b"proxy.corp me@home me@corp me@home"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn template_secrets() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let secret_keys = [SecretKey::generate()];
+    let other_keys = [SecretKey::generate()];
+    let context = MachineContext {
+        recipient: secret_keys[0].as_recipient("laptop"),
+        variables: HashMap::new(),
+    };
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_keys[0].as_recipient("laptop"),
+    )?;
+    assert!(repository.secrets(&secret_keys)?.is_empty());
+
+    repository.store_secrets(&Secrets::from([(
+        "token".to_string(),
+        "s3cr3t".to_string(),
+    )]))?;
+    assert!(repository.secrets(&other_keys).is_err());
+
+    let other = other_keys[0].as_recipient("desktop");
+    repository.add_recipient_request(other.clone());
+    repository.approve_recipients(&[other], &secret_keys)?;
+
+    assert_eq!(repository.secrets(&other_keys)?["token"], "s3cr3t");
+
+    let added = repository.add_files(
+        FileAction::AsIs,
+        None,
+        None,
+        vec![PathBuf::from(".config/someapp/config")],
+    )?;
+    let file_ref = repository.change_action(&added[0], FileAction::J2, &secret_keys)?;
+    file_ref.set_content(b"token={{ secrets.token }}")?;
+
+    assert_eq!(
+        file_ref.get_rendered(&context, &secret_keys)?,
+        b"token=s3cr3t"
+    );
+
+    // Machines missing from the secrets can still render templates without secrets
+    let unknown_keys = [SecretKey::generate()];
+    repository.store()?;
+    let repository = Repository::<TestEnvironment>::open(tmp_repo.path())?;
+    let file_ref = repository.files().next().ok_or("file missing")?;
+    let error = file_ref
+        .get_rendered(&context, &unknown_keys)
+        .expect_err("secrets are not readable");
+    assert!(error.is::<SecretsUnavailable>());
+    assert!(error.to_string().contains("Cannot decrypt secrets"));
+    file_ref.set_content(b"recipient={{ recipient }}")?;
+    assert_eq!(
+        file_ref.get_rendered(&context, &unknown_keys)?,
+        b"recipient=laptop"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn crypted_templates() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let secret_keys = [SecretKey::generate()];
+    let other_keys = [SecretKey::generate()];
+    let context = MachineContext {
+        recipient: secret_keys[0].as_recipient("laptop"),
+        variables: HashMap::from([("user".to_string(), "me".to_string())]),
+    };
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_keys[0].as_recipient("laptop"),
+    )?;
+
+    let added = repository.add_files(
+        FileAction::CryptedJ2,
+        None,
+        None,
+        vec![PathBuf::from(".config/someotherapp/secret_config")],
+    )?;
+    repository.store()?;
+
+    assert!(
+        fs::read(added[0].absolute_source())?.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----")
+    );
+    assert!(added[0]
+        .get_rendered(&context, &secret_keys)?
+        .starts_with(b"This is\na very\nsecret config\n"));
+
+    added[0].set_content(b"login {{ machine.user }} password hunter2")?;
+
+    let other = other_keys[0].as_recipient("desktop");
+    repository.add_recipient_request(other.clone());
+    repository.approve_recipients(&[other], &secret_keys)?;
+
+    assert_eq!(
+        added[0].get_content(&other_keys)?,
+        b"login {{ machine.user }} password hunter2"
+    );
+    assert_eq!(
+        added[0].get_rendered(&context, &other_keys)?,
+        b"login me password hunter2"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn shared_templates() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let secret_keys = [SecretKey::generate()];
+    let context = MachineContext {
+        recipient: secret_keys[0].as_recipient("laptop"),
+        variables: HashMap::new(),
+    };
+
+    let templates_dir = tmp_repo.path().join("_templates");
+    fs::create_dir_all(templates_dir.join("shell"))?;
+    fs::write(templates_dir.join("shell/aliases.j2"), "alias ll='ls -l'\n")?;
+    fs::write(
+        templates_dir.join("macros.j2"),
+        "{% macro greet(name) %}hello {{ name }}{% endmacro greet %}",
+    )?;
+    fs::write(
+        templates_dir.join("base.j2"),
+        "# {{ recipient }}\n{% block body %}{% endblock body %}",
+    )?;
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_keys[0].as_recipient("laptop"),
+    )?;
+    let added = repository.add_files(
+        FileAction::AsIs,
+        None,
+        None,
+        vec![PathBuf::from(".config/someapp/config")],
+    )?;
+    let file_ref = repository.change_action(&added[0], FileAction::J2, &secret_keys)?;
+    file_ref.set_content(
+        b"{% extends \"base.j2\" %}{% import \"macros.j2\" as macros %}{% block body %}{{ macros::greet(name=recipient) }}\n{% include \"shell/aliases.j2\" %}{% endblock body %}",
+    )?;
+
+    assert_eq!(
+        String::from_utf8(file_ref.get_rendered(&context, &secret_keys)?)?,
+        "# laptop\nhello laptop\nalias ll='ls -l'\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn system_context() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let secret_keys = [SecretKey::generate()];
+    let context = MachineContext {
+        recipient: secret_keys[0].as_recipient("laptop"),
+        variables: HashMap::new(),
+    };
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_keys[0].as_recipient("laptop"),
+    )?;
+    let added = repository.add_files(
+        FileAction::AsIs,
+        None,
+        None,
+        vec![PathBuf::from(".config/someapp/config")],
+    )?;
+    let file_ref = repository.change_action(&added[0], FileAction::J2, &secret_keys)?;
+    file_ref.set_content(
+        b"{{ system.username }}@{{ system.hostname }} ({{ system.uid }}, {{ system.distribution }}) {{ file.target }} {{ file.repository }}",
+    )?;
+
+    assert_eq!(
+        String::from_utf8(file_ref.get_rendered(&context, &secret_keys)?)?,
+        format!(
+            "tester@testhost (1234, testdistro) {} {}",
+            TestEnvironment::home_dir()?
+                .join(".config/someapp/config")
+                .to_string_lossy(),
+            tmp_repo.path().to_string_lossy()
+        )
+    );
+
+    Ok(())
+}
+
+#[test]
+fn migrate_format_version() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let secret_keys = [SecretKey::generate()];
+    let context = MachineContext {
+        recipient: secret_keys[0].as_recipient("laptop"),
+        variables: HashMap::new(),
+    };
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_keys[0].as_recipient("laptop"),
+    )?;
+    let added = repository.add_files(
+        FileAction::J2,
+        None,
+        None,
+        vec![PathBuf::from(".config/someapp/config")],
+    )?;
+    assert_eq!(added[0].file.source, "config.j2");
+    repository.store()?;
+
+    // Layout of version 0: descriptor without suffix, file written with suffix
+    let root_file = tmp_repo.path().join("dotium.json");
+    let dir_file = tmp_repo.path().join("config/someapp/dotium_dir.json");
+    let root = fs::read_to_string(&root_file)?.replace("\"format_version\": 1,", "");
+    fs::write(&root_file, root)?;
+    let dir = fs::read_to_string(&dir_file)?.replace("config.j2", "config");
+    fs::write(&dir_file, dir)?;
+
+    let mut repository = Repository::<TestEnvironment>::open(tmp_repo.path())?;
+    assert_eq!(repository.format_version(), 0);
+    let migrations = repository.migrations()?;
+    assert_eq!(migrations.len(), 2);
+
+    repository.migrate(&migrations)?;
+    repository.store()?;
+
+    let repository = Repository::<TestEnvironment>::open(tmp_repo.path())?;
+    assert_eq!(repository.format_version(), FORMAT_VERSION);
+    assert!(repository.migrations()?.is_empty());
+    let file_ref = repository.files().next().ok_or("file missing")?;
+    assert_eq!(file_ref.file.source, "config.j2");
+    assert_eq!(
+        file_ref.get_rendered(&context, &secret_keys)?,
+        b"This is some\nfancy \nconfig file\nthat\ndoes not\nrequire\nprotection\n"
+    );
+
+    let root = fs::read_to_string(&root_file)?.replace(
+        &format!("\"format_version\": {FORMAT_VERSION}"),
+        &format!("\"format_version\": {}", FORMAT_VERSION + 1),
+    );
+    fs::write(&root_file, root)?;
+    assert!(Repository::<TestEnvironment>::open(tmp_repo.path()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn check_repository() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let secret_keys = [SecretKey::generate()];
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_keys[0].as_recipient("laptop"),
+    )?;
+    let templates = repository.add_files(
+        FileAction::J2,
+        None,
+        None,
+        vec![PathBuf::from(".config/someapp/config")],
+    )?;
+    repository.add_files(
+        FileAction::Crypted,
+        None,
+        None,
+        vec![PathBuf::from(".config/someotherapp/secret_config")],
+    )?;
+    repository.store()?;
+
+    assert!(repository.check(Some(&secret_keys))?.is_empty());
+
+    let other_keys = [SecretKey::generate()];
+    let issues = repository.check(Some(&other_keys))?;
+    assert_eq!(issues.len(), 1);
+    assert!(matches!(issues[0].issue, Issue::CannotDecrypt { .. }));
+    assert_eq!(
+        issues[0].path,
+        PathBuf::from("config/someotherapp/secret_config")
+    );
+    // Without keys only the structure is checked
+    assert!(repository.check(None)?.is_empty());
+
+    templates[0].set_content(b"{% if %}")?;
+    fs::write(tmp_repo.path().join("config/someapp/stray"), b"stray")?;
+    fs::remove_file(tmp_repo.path().join("config/someotherapp/secret_config"))?;
+    repository.root.directories.push(PathBuf::from("ghost"));
+
+    let issues = repository.check(Some(&secret_keys))?;
+    let kinds = issues
+        .iter()
+        .map(|entry| serde_json::to_value(entry).map(|value| value["issue"].to_string()))
+        .collect::<Result<Vec<String>, _>>()?;
+    assert_eq!(
+        kinds,
+        vec![
+            "\"missing-descriptor\"",
+            "\"invalid-template\"",
+            "\"missing-source\"",
+            "\"orphan-file\""
+        ]
+    );
+    assert_eq!(issues[3].path, PathBuf::from("config/someapp/stray"));
+
+    Ok(())
+}
+
+#[test]
+fn stale_encryption() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let key_file = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join("keys")
+        .join("id_ed25519");
+    let ssh_keys = SecretKey::read_from(fs::File::open(&key_file)?, None)?;
+    let secret_keys = [SecretKey::generate()];
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_keys[0].as_recipient("laptop"),
+    )?;
+    repository.add_files(
+        FileAction::Crypted,
+        None,
+        None,
+        vec![PathBuf::from(".config/someotherapp/secret_config")],
+    )?;
+    repository.store_secrets(&Secrets::from([("token".to_string(), "1234".to_string())]))?;
+    repository.store()?;
+
+    let file_ref = repository.files().next().ok_or("file missing")?;
+    assert!(!file_ref.is_stale()?);
+    assert!(!repository.secrets_stale()?);
+
+    // Like a manual edit of dotium.json
+    repository
+        .root
+        .recipients
+        .push(ssh_keys[0].as_recipient("server"));
+    repository.refresh_info();
+
+    let file_ref = repository.files().next().ok_or("file missing")?;
+    assert!(file_ref.is_stale()?);
+    assert!(repository.secrets_stale()?);
+    let issues = repository.check(Some(&secret_keys))?;
+    assert_eq!(issues.len(), 2);
+    assert!(issues
+        .iter()
+        .all(|entry| matches!(entry.issue, Issue::StaleEncryption)));
+
+    repository.reencrypt_files(&[file_ref], true, &secret_keys)?;
+
+    let file_ref = repository.files().next().ok_or("file missing")?;
+    assert!(!file_ref.is_stale()?);
+    assert!(!repository.secrets_stale()?);
+    assert!(file_ref
+        .get_content(&ssh_keys)?
+        .starts_with(b"This is\na very\nsecret config\n"));
+
+    fs::write(tmp_repo.path().join(SECRETS_FILE), b"garbage")?;
+    let issues = repository.check(None)?;
+    assert_eq!(issues.len(), 1);
+    assert!(matches!(issues[0].issue, Issue::Unreadable { .. }));
+
+    Ok(())
+}
+
+#[test]
+fn record_changed_files() -> Result<(), Box<dyn Error>> {
+    let tmp_repo = tempfile::tempdir()?;
+    let secret_keys = [SecretKey::generate()];
+
+    let mut repository = Repository::<TestEnvironment>::init(
+        tmp_repo.path().to_path_buf(),
+        secret_keys[0].as_recipient("laptop"),
+    )?;
+    let added = repository.add_files(
+        FileAction::AsIs,
+        None,
+        None,
+        vec![PathBuf::from(".config/someapp/config")],
+    )?;
+    repository.change_action(&added[0], FileAction::Crypted, &secret_keys)?;
+    repository.store()?;
+
+    assert_eq!(
+        repository.changed_files(),
+        vec![
+            PathBuf::from("config/someapp/config"),
+            PathBuf::from("config/someapp/dotium_dir.json"),
+            PathBuf::from("dotium.json"),
+        ]
+    );
+
+    let repository = Repository::<TestEnvironment>::open(tmp_repo.path())?;
+    assert!(repository.changed_files().is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Changes in the git working copy of a repository.
+#[derive(Debug, Default)]
+pub struct WorkingCopy {
+    pub uncommitted: Vec<String>,
+    /// Files with merge conflicts
+    pub unmerged: Vec<String>,
+}
+
+/// Whether the directory is inside a git working copy (and git is available at all).
+pub fn is_work_tree(directory: &Path) -> bool {
+    Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(["rev-parse", "--is-inside-work-tree"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Changes of the working copy below the directory.
+pub fn working_copy(directory: &Path) -> Result<WorkingCopy, Box<dyn Error>> {
+    let output = git(directory, &["status", "--porcelain", "--", "."], &[])?;
+    let mut working_copy = WorkingCopy::default();
+
+    for line in output.lines() {
+        let (state, path) = match (line.get(..2), line.get(3..)) {
+            (Some(state), Some(path)) => (state, path.to_string()),
+            _ => continue,
+        };
+
+        if state.contains('U') || state == "AA" || state == "DD" {
+            working_copy.unmerged.push(path);
+        } else {
+            working_copy.uncommitted.push(path);
+        }
+    }
+
+    Ok(working_copy)
+}
+
+/// Fast-forward the working copy to its upstream.
+pub fn pull(directory: &Path) -> Result<(), Box<dyn Error>> {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(["pull", "--ff-only"])
+        .status()?;
+
+    if !status.success() {
+        return Err(format!("git pull failed ({status})").into());
+    }
+
+    Ok(())
+}
+
+/// Commit exactly the given files (relative to the directory), other staged changes are left alone.
+///
+/// Returns `false` if none of the files actually changed.
+pub fn commit(directory: &Path, files: &[PathBuf], message: &str) -> Result<bool, Box<dyn Error>> {
+    let (existing, removed): (Vec<&PathBuf>, Vec<&PathBuf>) =
+        files.iter().partition(|file| directory.join(file).exists());
+
+    if !existing.is_empty() {
+        git(directory, &["add", "--all", "--"], &existing)?;
+    }
+    if !removed.is_empty() {
+        git(
+            directory,
+            &["rm", "--cached", "--quiet", "--ignore-unmatch", "--"],
+            &removed,
+        )?;
+    }
+
+    // Files that were never known to git cannot be passed to commit
+    let staged = git(
+        directory,
+        &["diff", "--cached", "--name-only", "--relative", "--"],
+        &files.iter().collect::<Vec<&PathBuf>>(),
+    )?;
+    let staged = staged.lines().map(PathBuf::from).collect::<Vec<PathBuf>>();
+    if staged.is_empty() {
+        return Ok(false);
+    }
+
+    git(
+        directory,
+        &["commit", "--quiet", "--message", message, "--"],
+        &staged.iter().collect::<Vec<&PathBuf>>(),
+    )?;
+
+    Ok(true)
+}
+
+fn git(directory: &Path, args: &[&str], files: &[&PathBuf]) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(args)
+        .args(files)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
@@ -1 +1,6 @@
 pub mod color_diff;
+pub mod git;
+pub mod template_patch;
+#[cfg(test)]
+mod tests;
+pub mod unified_diff;
@@ -0,0 +1,187 @@
+/// Apply changes of a rendered template back to the template itself.
+///
+/// Only works if every changed line of the rendered output corresponds to a literal line
+/// of the template, i.e. a line without any tags that is not inside a block (`if`, `for`,
+/// `macro` ...). Returns `None` if that is not the case.
+///
+/// The result is a best guess, callers should check that it renders to `changed`.
+pub fn patch_template(template: &str, rendered: &str, changed: &str) -> Option<String> {
+    let template_lines = template.split_inclusive('\n').collect::<Vec<&str>>();
+    let rendered_lines = rendered.split_inclusive('\n').collect::<Vec<&str>>();
+    let changed_lines = changed.split_inclusive('\n').collect::<Vec<&str>>();
+
+    let literal = literal_lines(&template_lines);
+    let origins = template_origins(&template_lines, &rendered_lines);
+    // Template line a rendered line originates from, if it is a literal one
+    let literal_origin = |index: usize| origins[index].filter(|origin| literal[*origin]);
+
+    let mut edits = Vec::new();
+    for hunk in hunks(&rendered_lines, &changed_lines) {
+        let (start, end) = if hunk.start < hunk.end {
+            let first = literal_origin(hunk.start)?;
+            let mut last = first;
+
+            for index in hunk.start + 1..hunk.end {
+                let origin = literal_origin(index)?;
+                if origin != last + 1 {
+                    return None;
+                }
+                last = origin;
+            }
+            (first, last + 1)
+        } else if hunk.start < rendered_lines.len() {
+            let next = literal_origin(hunk.start).or_else(|| {
+                hunk.start
+                    .checked_sub(1)
+                    .and_then(literal_origin)
+                    .map(|i| i + 1)
+            })?;
+            (next, next)
+        } else if hunk.start > 0 {
+            let previous = literal_origin(hunk.start - 1)?;
+            (previous + 1, previous + 1)
+        } else {
+            (template_lines.len(), template_lines.len())
+        };
+
+        edits.push((start, end, hunk.lines));
+    }
+
+    let mut result = template_lines;
+    for (start, end, lines) in edits.into_iter().rev() {
+        result.splice(start..end, lines);
+    }
+
+    Some(result.concat())
+}
+
+struct Hunk<'a> {
+    /// Range of replaced lines of the rendered output (empty for pure insertions)
+    start: usize,
+    end: usize,
+    lines: Vec<&'a str>,
+}
+
+fn hunks<'a>(rendered: &[&'a str], changed: &[&'a str]) -> Vec<Hunk<'a>> {
+    let mut hunks: Vec<Hunk<'a>> = Vec::new();
+    let mut index = 0;
+    let mut in_hunk = false;
+
+    for result in diff::slice(rendered, changed) {
+        match result {
+            diff::Result::Both(_, _) => {
+                index += 1;
+                in_hunk = false;
+            }
+            diff::Result::Left(_) => {
+                if !in_hunk {
+                    hunks.push(Hunk {
+                        start: index,
+                        end: index,
+                        lines: vec![],
+                    });
+                    in_hunk = true;
+                }
+                index += 1;
+                if let Some(hunk) = hunks.last_mut() {
+                    hunk.end = index;
+                }
+            }
+            diff::Result::Right(line) => {
+                if !in_hunk {
+                    hunks.push(Hunk {
+                        start: index,
+                        end: index,
+                        lines: vec![],
+                    });
+                    in_hunk = true;
+                }
+                if let Some(hunk) = hunks.last_mut() {
+                    hunk.lines.push(line);
+                }
+            }
+        }
+    }
+
+    hunks
+}
+
+/// For every rendered line the template line it was copied from (if any).
+fn template_origins(template: &[&str], rendered: &[&str]) -> Vec<Option<usize>> {
+    let mut origins = vec![None; rendered.len()];
+    let (mut template_index, mut rendered_index) = (0, 0);
+
+    for result in diff::slice(template, rendered) {
+        match result {
+            diff::Result::Both(_, _) => {
+                origins[rendered_index] = Some(template_index);
+                template_index += 1;
+                rendered_index += 1;
+            }
+            diff::Result::Left(_) => template_index += 1,
+            diff::Result::Right(_) => rendered_index += 1,
+        }
+    }
+
+    origins
+}
+
+/// Whether each template line is rendered as is, i.e. has no tags and is not inside a block.
+fn literal_lines(template: &[&str]) -> Vec<bool> {
+    let mut literal = Vec::with_capacity(template.len());
+    let mut depth = 0usize;
+    let mut in_tag = false;
+
+    for line in template {
+        let mut is_literal = depth == 0 && !in_tag;
+        let mut rest = *line;
+
+        loop {
+            if in_tag {
+                match ["}}", "%}", "#}"]
+                    .iter()
+                    .filter_map(|end| rest.find(end))
+                    .min()
+                {
+                    Some(end) => {
+                        rest = &rest[end + 2..];
+                        in_tag = false;
+                    }
+                    None => break,
+                }
+            } else {
+                match ["{{", "{%", "{#"]
+                    .iter()
+                    .filter_map(|start| rest.find(start).map(|index| (index, *start)))
+                    .min()
+                {
+                    Some((start, kind)) => {
+                        is_literal = false;
+                        rest = &rest[start + 2..];
+                        in_tag = true;
+
+                        if kind == "{%" {
+                            let tag = rest.trim_start_matches('-').trim_start();
+                            let name = tag.split_whitespace().next().unwrap_or_default();
+
+                            if name.starts_with("end") {
+                                depth = depth.saturating_sub(1);
+                            } else if ["if", "for", "macro", "block", "filter", "raw"]
+                                .contains(&name)
+                                || (name == "set"
+                                    && !tag.split("%}").next().unwrap_or_default().contains('='))
+                            {
+                                depth += 1;
+                            }
+                        }
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        literal.push(is_literal);
+    }
+
+    literal
+}
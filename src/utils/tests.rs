@@ -0,0 +1,110 @@
+use std::{error::Error, fs, path::PathBuf, process::Command};
+
+use super::{git, template_patch::patch_template, unified_diff::UnifiedDiff};
+
+#[test]
+fn unified_diff_equal() {
+    let content = "a\nb\nc\n";
+
+    assert_eq!(
+        UnifiedDiff::new("a/file", "b/file", content, content).to_string(),
+        ""
+    );
+}
+
+#[test]
+fn unified_diff_single_hunk() {
+    let old = "1\n2\n3\n4\n5\n6\n7\n8\n";
+    let new = "1\n2\n3\n4\nfive\n6\n7\n8\n";
+
+    assert_eq!(
+        UnifiedDiff::new("a/file", "b/file", old, new).to_string(),
+        "--- a/file\n+++ b/file\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+    );
+}
+
+#[test]
+fn unified_diff_separate_hunks() {
+    let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+    let new = "one\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n";
+
+    assert_eq!(
+        UnifiedDiff::new("a/file", "b/file", old, new).to_string(),
+        "--- a/file\n+++ b/file\n@@ -1,4 +1,4 @@\n-1\n+one\n 2\n 3\n 4\n@@ -10,3 +10,4 @@\n 10\n 11\n 12\n+13\n"
+    );
+}
+
+#[test]
+fn unified_diff_new_file_without_newline() {
+    assert_eq!(
+        UnifiedDiff::new("/dev/null", "b/file", "", "first\nsecond").to_string(),
+        "--- /dev/null\n+++ b/file\n@@ -0,0 +1,2 @@\n+first\n+second\n\\ No newline at end of file\n"
+    );
+}
+
+#[test]
+fn patch_template_literal_lines() {
+    let template =
+        "# {{ system.hostname }}\nalias ll='ls -l'\n{% if work %}\nproxy=1\n{% endif %}\nend\n";
+    let rendered = "# box\nalias ll='ls -l'\n\nproxy=1\n\nend\n";
+    let changed = "# box\nalias ll='ls -la'\nalias g=git\n\nproxy=1\n\nend\nnew\n";
+
+    assert_eq!(
+        patch_template(template, rendered, changed).as_deref(),
+        Some("# {{ system.hostname }}\nalias ll='ls -la'\nalias g=git\n{% if work %}\nproxy=1\n{% endif %}\nend\nnew\n")
+    );
+}
+
+#[test]
+fn patch_template_inside_tags() {
+    let template = "# {{ system.hostname }}\n{% if work %}\nproxy=1\n{% endif %}\n";
+    let rendered = "# box\n\nproxy=1\n\n";
+
+    assert_eq!(
+        patch_template(template, rendered, "# laptop\n\nproxy=1\n\n"),
+        None
+    );
+    assert_eq!(
+        patch_template(template, rendered, "# box\n\nproxy=2\n\n"),
+        None
+    );
+}
+
+#[test]
+fn git_commit_only_given_files() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = tempfile::tempdir()?;
+    let directory = tmp_dir.path();
+    let run = |args: &[&str]| {
+        Command::new("git")
+            .arg("-C")
+            .arg(directory)
+            .args(args)
+            .status()
+    };
+
+    // Nothing to check without git
+    if !run(&["init", "--quiet"]).is_ok_and(|status| status.success()) {
+        return Ok(());
+    }
+    run(&["config", "user.name", "test"])?;
+    run(&["config", "user.email", "test@example.com"])?;
+    assert!(git::is_work_tree(directory));
+
+    fs::write(directory.join("tracked"), "tracked")?;
+    fs::write(directory.join("other"), "other")?;
+    run(&["add", "other"])?;
+
+    let files = [PathBuf::from("tracked"), PathBuf::from("never-existed")];
+    assert!(git::commit(directory, &files, "track file")?);
+    assert!(!git::commit(directory, &files, "nothing changed")?);
+
+    let working_copy = git::working_copy(directory)?;
+    assert_eq!(working_copy.uncommitted, vec!["other"]);
+    assert!(working_copy.unmerged.is_empty());
+
+    fs::remove_file(directory.join("tracked"))?;
+    assert!(git::commit(directory, &files, "untrack file")?);
+    assert_eq!(git::working_copy(directory)?.uncommitted, vec!["other"]);
+
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+
+static DEFAULT_CONTEXT: usize = 3;
+
+/// Plain unified diff (as produced by `diff -u`) of two multiline strings.
+///
+/// The output is suitable for `patch` and code review tools. If both sides are
+/// equal nothing is written at all.
+pub struct UnifiedDiff<'a> {
+    old_name: &'a str,
+    new_name: &'a str,
+    old: &'a str,
+    new: &'a str,
+    context: usize,
+}
+
+impl<'a> UnifiedDiff<'a> {
+    pub fn new(old_name: &'a str, new_name: &'a str, old: &'a str, new: &'a str) -> Self {
+        UnifiedDiff {
+            old_name,
+            new_name,
+            old,
+            new,
+            context: DEFAULT_CONTEXT,
+        }
+    }
+}
+
+impl fmt::Display for UnifiedDiff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_unified(
+            f,
+            self.old_name,
+            self.new_name,
+            self.old,
+            self.new,
+            self.context,
+        )
+    }
+}
+
+fn write_unified<TWrite: fmt::Write>(
+    f: &mut TWrite,
+    old_name: &str,
+    new_name: &str,
+    old: &str,
+    new: &str,
+    context: usize,
+) -> fmt::Result {
+    // Keep the line endings so that a missing newline at the end of file can be reported
+    let old_lines = old.split_inclusive('\n').collect::<Vec<&str>>();
+    let new_lines = new.split_inclusive('\n').collect::<Vec<&str>>();
+    let ops = ::diff::slice(&old_lines, &new_lines);
+
+    let changes = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, ::diff::Result::Both(_, _)))
+        .map(|(index, _)| index)
+        .collect::<Vec<usize>>();
+
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    // Group changes into hunks, merging them if their context overlaps
+    let mut hunks = Vec::new();
+    let mut start = changes[0].saturating_sub(context);
+    let mut end = (changes[0] + context + 1).min(ops.len());
+
+    for &change in &changes[1..] {
+        if change.saturating_sub(context) <= end {
+            end = (change + context + 1).min(ops.len());
+        } else {
+            hunks.push((start, end));
+            start = change.saturating_sub(context);
+            end = (change + context + 1).min(ops.len());
+        }
+    }
+    hunks.push((start, end));
+
+    writeln!(f, "--- {old_name}")?;
+    writeln!(f, "+++ {new_name}")?;
+
+    for (start, end) in hunks {
+        let old_before = ops[..start]
+            .iter()
+            .filter(|op| !matches!(op, ::diff::Result::Right(_)))
+            .count();
+        let new_before = ops[..start]
+            .iter()
+            .filter(|op| !matches!(op, ::diff::Result::Left(_)))
+            .count();
+        let old_len = ops[start..end]
+            .iter()
+            .filter(|op| !matches!(op, ::diff::Result::Right(_)))
+            .count();
+        let new_len = ops[start..end]
+            .iter()
+            .filter(|op| !matches!(op, ::diff::Result::Left(_)))
+            .count();
+
+        writeln!(
+            f,
+            "@@ -{},{} +{},{} @@",
+            hunk_start(old_before, old_len),
+            old_len,
+            hunk_start(new_before, new_len),
+            new_len
+        )?;
+
+        for op in &ops[start..end] {
+            match op {
+                ::diff::Result::Both(line, _) => write_line(f, ' ', line)?,
+                ::diff::Result::Left(line) => write_line(f, '-', line)?,
+                ::diff::Result::Right(line) => write_line(f, '+', line)?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Line numbers in hunk headers are 1-based, an empty range refers to the line before.
+fn hunk_start(before: usize, len: usize) -> usize {
+    if len == 0 {
+        before
+    } else {
+        before + 1
+    }
+}
+
+fn write_line<TWrite: fmt::Write>(f: &mut TWrite, prefix: char, line: &str) -> fmt::Result {
+    if let Some(line) = line.strip_suffix('\n') {
+        writeln!(f, "{prefix}{line}")
+    } else {
+        writeln!(f, "{prefix}{line}")?;
+        writeln!(f, "\\ No newline at end of file")
+    }
+}